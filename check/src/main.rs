@@ -16,52 +16,14 @@
 //    the passed start date, or those for the specified duration where
 //    the start date is today
 
-use anyhow;
 use chrono::prelude::*;
 use chrono::Duration;
 use schedule_assistant::authentication::AuthenticationCache;
-use schedule_assistant::{current_rms, servicem8};
-use serde::Deserialize;
-
-// Data Structures
-// ---------------
-
-#[derive(Clone, Deserialize)]
-pub struct MemberEmail {
-    address: String, 
-    type_id: u32,
-    email_type_name: String, 
-    id: u32
-}
-
-#[derive(Clone, Deserialize)]
-pub struct Member {
-    uuid: u32,
-    name: String,
-    active: bool,
-    emails: Vec<MemberEmail>,
-}
-
-#[derive(Clone, Deserialize)]
-pub struct Opportunity {
-    id: u32,
-    member_id: u32, //<?? Interestingly if we have the member_id's etc at this level, do we need the member structs as part of this structure?
-    member: Member,
-    subject: String,
-    starts_at: DateTime<Utc>, //< We need to do some fancy shit for this to serialize properly
-    end_at: DateTime<Utc>,
-    state: u32,
-    state_name: String,
-    status: u32,
-    status_name: String,
-}
-
-#[derive(Deserialize)]
-pub struct Job {
-    uuid: String,
-}
-
-
+use schedule_assistant::dataset::Dataset;
+use schedule_assistant::links::LinkStore;
+use schedule_assistant::models::{Job, Opportunity, STATE_ORDER};
+use schedule_assistant::servicem8::Tables;
+use schedule_assistant::{current_rms, reconcile, references, servicem8, Explanation, Mismatch};
 
 // Functions
 // ----------
@@ -75,17 +37,23 @@ pub struct Job {
 // [x] calculate start & length from command line arguments
 // [] pull current_rms::opportunity's from current_rms endpoint
 // [] filter opportunities by optional date parameters
-// [] pull servicem8::job's from servicem8 endpoint
-// [] iterate current_rms::opportunity's and find matching servicem8::job
-// [] list any opportunities that doesnt have a matching job
+// [x] pull servicem8::job's from servicem8 endpoint
+// [x] iterate current_rms::opportunity's and find matching servicem8::job
+// [x] list any opportunities that doesnt have a matching job
+// [x] optionally (--write-back) store each match in both records
+// [x] optionally (--reconcile) list the activities of matched jobs that need
+//     to be moved to line up with their opportunity
 pub fn main() -> anyhow::Result<()> {
-    dotenv::dotenv().expect("Failed to read .env file");
+    dotenv::dotenv().ok();
 
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let write_back = args.iter().any(|arg| arg == "--write-back");
+    let reconcile = args.iter().any(|arg| arg == "--reconcile");
+    args.retain(|arg| arg != "--write-back" && arg != "--reconcile");
     let start = if args.len() > 1 {
-        Utc.datetime_from_str(&args[1], "%Y-%m-%d %H:%M:%S")?.date() //"2021-03-22 00:00:00"
+        NaiveDateTime::parse_from_str(&args[1], "%Y-%m-%d %H:%M:%S")?.date() //"2021-03-22 00:00:00"
     } else {
-        Utc::now().date()
+        Utc::now().date_naive()
     };
     let length = if args.len() > 2 {
         args[2].parse::<i64>()?
@@ -94,18 +62,38 @@ pub fn main() -> anyhow::Result<()> {
     };
     let duration = Duration::days(length);
     let (start_date, end_date) = calculate_window(start, duration);
-    let auth_cache = AuthenticationCache::new();
-    let opportunities = gather_opportunities(&auth_cache, start_date, end_date);
-    let jobs = gather_jobs(&auth_cache);
-    let unscheduled_jobs = check_scheduled(&opportunities, &jobs);
-    print_opportunities(&unscheduled_jobs);
+    let auth_cache = AuthenticationCache::new()?;
+    let opportunities = gather_opportunities(&auth_cache, start_date, end_date)?;
+    let tables = gather_jobs(&auth_cache)?;
+    let links = LinkStore::from_env()?;
+    let checked = check_scheduled(&links, &opportunities, &tables)?;
+    let failures = if write_back {
+        write_back_matches(&auth_cache, &checked, &tables.jobs)
+    } else {
+        Vec::new()
+    };
+    if reconcile {
+        print_reschedules(&links, &checked, &tables);
+    }
+    let unscheduled_jobs = checked
+        .into_iter()
+        .filter(|(_, explanation)| !matches!(explanation, Explanation::Matched { .. }))
+        .collect::<Vec<_>>();
+    print_opportunities(&unscheduled_jobs, &tables.jobs);
+
+    if !failures.is_empty() {
+        for (opportunity_id, e) in &failures {
+            eprintln!("opportunity {}: {}", opportunity_id, e);
+        }
+        anyhow::bail!("{} matches couldn't be written back", failures.len());
+    }
     Ok(())
 }
 
 // calculate_window
 //
 // converts a start and duration to a start and end date
-fn calculate_window(start: Date<Utc>, duration: Duration) -> (Date<Utc>, Date<Utc>) {
+fn calculate_window(start: NaiveDate, duration: Duration) -> (NaiveDate, NaiveDate) {
     let end = start + duration;
     (start, end)
 }
@@ -122,47 +110,121 @@ fn calculate_window(start: Date<Utc>, duration: Duration) -> (Date<Utc>, Date<Ut
 // [x] filter for those that fit in the specified window
 //
 // note:
-// - Can we restrict the retrieval to the specified information or
-//   do we need to do that post retrieval?
+// - Current RMS restricts the retrieval to confirmed orders roughly within
+//   the window, the exact bounds are then applied post retrieval.
 fn gather_opportunities(
     auth_cache: &AuthenticationCache,
-    start_date: Date<Utc>,
-    end_date: Date<Utc>,
-) -> Vec<Opportunity> {
-    let opportunities = current_rms::opportunities(auth_cache).unwrap();
-    opportunities
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+) -> schedule_assistant::Result<Vec<Opportunity>> {
+    let query = current_rms::Query::new()
+        .state(STATE_ORDER)
+        .starts_from(&start_date)
+        .ends_before(&end_date);
+    let opportunities = current_rms::opportunities_matching(auth_cache, &query)?;
+    Ok(opportunities
         .into_iter()
         .filter(current_rms::opportunity_is_confirmed)
         .filter(|opportunity| {
-            schedule_assistant::opportunity_within_date_range(&opportunity, &start_date, &end_date)
-                .unwrap_or(false)
+            schedule_assistant::opportunity_within_date_range(opportunity, &start_date, &end_date)
         })
-        .map(|opportunity| serde_json::from_value(opportunity).unwrap())
-        .collect::<Vec<Opportunity>>()
+        .collect::<Vec<Opportunity>>())
 }
 
 // gather_jobs
 //
-// Retrieves all of the servicem8::job elements
+// Retrieves all of the servicem8::job elements along with the clients,
+// contacts and activities needed to match them against opportunities.
 //
 // tasks:
 // [x] establish connection to the servicem8 jobs endpoint,
 // [x] setup authorization
 // [x] retrieve all of the jobs
-fn gather_jobs(auth_cache: &AuthenticationCache) -> Vec<Job> {
-    servicem8::jobs(auth_cache)
-        .unwrap()
-        .into_iter()
-        .map(|value| serde_json::from_value(value).unwrap())
-        .collect()
+//
+// note:
+// - every activity is retrieved, not just those within the window, so that
+//   we can tell a job scheduled on the wrong dates from one with no activities.
+fn gather_jobs(auth_cache: &AuthenticationCache) -> schedule_assistant::Result<Tables> {
+    servicem8::tables(auth_cache)
 }
 
-// check_scheduled(opportunities_to_check, jobs)
+// check_scheduled(links, opportunities_to_check, tables)
 //
 // iterates over the opportunities_to_check, compares against jobs, outputs
-// a list of unscheduled opportunities.
-fn check_scheduled(opportunities_to_check: &Vec<Opportunity>, jobs: &Vec<Job>) -> Vec<Opportunity> {
-    opportunities_to_check.iter().cloned().collect()
+// each opportunity with the job that it matched or why no job matched.  Any
+// opportunity with a confirmed link is taken as scheduled.
+fn check_scheduled(
+    links: &LinkStore,
+    opportunities_to_check: &[Opportunity],
+    tables: &Tables,
+) -> schedule_assistant::Result<Vec<(Opportunity, Explanation)>> {
+    let dataset = Dataset::from_tables(tables);
+    let mut checked = Vec::new();
+    for opportunity in opportunities_to_check {
+        let explanation = dataset.explain(links, opportunity)?;
+        checked.push((opportunity.clone(), explanation));
+    }
+    Ok(checked)
+}
+
+// write_back_matches(auth_cache, checked, jobs)
+//
+// stores the job against each matched opportunity, and the opportunity against
+// the job, so that later runs don't have to match them again.  Carries on past
+// any that fail, returning them with the reason.
+fn write_back_matches(
+    auth_cache: &AuthenticationCache,
+    checked: &[(Opportunity, Explanation)],
+    jobs: &[Job],
+) -> Vec<(u64, schedule_assistant::Error)> {
+    let mut failures = Vec::new();
+    for (opportunity, explanation) in checked {
+        let uuid = match explanation {
+            Explanation::Matched { job, .. } => job,
+            _ => continue,
+        };
+        // A match found from one side's reference still needs the other's.
+        let job = jobs.iter().find(|job| &job.uuid == uuid);
+        if let Some(job) = job.filter(|job| !references::refer_to_each_other(opportunity, job)) {
+            println!(
+                "Storing job {} against opportunity {}",
+                job_number(jobs, uuid),
+                opportunity.id
+            );
+            if let Err(e) = references::write_back(auth_cache, opportunity, job) {
+                println!("  unable to store it: {}", e);
+                failures.push((opportunity.id, e));
+            }
+        }
+    }
+    failures
+}
+
+// print_reschedules(links, checked, tables)
+//
+// lists the delivery and collection activities of each opportunity's job which
+// have drifted away from its dates, including jobs that have drifted too far to
+// be matched.
+fn print_reschedules(links: &LinkStore, checked: &[(Opportunity, Explanation)], tables: &Tables) {
+    let dataset = Dataset::from_tables(tables);
+    let reschedules = checked
+        .iter()
+        .filter_map(|(opportunity, _)| {
+            Some((opportunity, dataset.job_to_reconcile(links, opportunity)?))
+        })
+        .flat_map(|(opportunity, job)| {
+            reconcile::reconcile_job(opportunity, &job.uuid, dataset.activities(job))
+        })
+        .collect::<Vec<_>>();
+
+    println!("{} activities need to be rescheduled", reschedules.len());
+    for reschedule in reschedules {
+        println!(
+            "  job {}: {}",
+            job_number(&tables.jobs, &reschedule.job_uuid),
+            reschedule
+        );
+    }
 }
 
 // print_opportunities
@@ -170,41 +232,62 @@ fn check_scheduled(opportunities_to_check: &Vec<Opportunity>, jobs: &Vec<Job>) -
 // Displays details of a collection of current_rms::Opportunity items
 //
 // @tasks:
-// [] Determine what details to show?
-// [] Show details of given opportunities to console.
-fn print_opportunities(opportunities: &Vec<Opportunity>) {
-    for opportunity in opportunities {
-        println!("{} ({})", opportunity.member.name, opportunity.id);
+// [x] Determine what details to show?
+// [x] Show details of given opportunities to console.
+//
+// note:
+// - jobs which are for someone else, or for another opportunity, are
+//   summarised rather than listed, only those for the member are shown
+//   individually.
+fn print_opportunities(opportunities: &[(Opportunity, Explanation)], jobs: &[Job]) {
+    for (opportunity, explanation) in opportunities {
+        let member_name = opportunity
+            .member
+            .as_ref()
+            .map(|member| member.name.as_str())
+            .unwrap_or("Unknown");
+        println!("{} ({})", member_name, opportunity.id);
+
+        match explanation {
+            Explanation::Matched { .. } => (),
+            Explanation::LinkedJobMissing { job } => {
+                println!("  linked to job {} which no longer exists", job)
+            }
+            Explanation::NoMember => println!("  the opportunity has no member to match"),
+            Explanation::NoJobs => println!("  there are no jobs in ServiceM8"),
+            Explanation::Unmatched(mismatches) => {
+                let (by_name, others): (Vec<_>, Vec<_>) = mismatches
+                    .iter()
+                    .partition(|(_, mismatch)| mismatch.is_name_mismatch());
+                let (for_others, others): (Vec<_>, Vec<_>) =
+                    others.into_iter().partition(|(_, mismatch)| {
+                        matches!(mismatch, Mismatch::OtherOpportunity { .. })
+                    });
+                for (uuid, mismatch) in &others {
+                    println!("  job {}: {}", job_number(jobs, uuid), mismatch);
+                }
+                if others.is_empty() {
+                    let with_contacts = by_name
+                        .iter()
+                        .filter(|(_, mismatch)| matches!(mismatch, Mismatch::Contacts { .. }))
+                        .count();
+                    println!(
+                        "  no client is named '{}' ({} jobs) and no contacts match ({} jobs with contacts), {} jobs are for other opportunities",
+                        member_name,
+                        by_name.len(),
+                        with_contacts,
+                        for_others.len()
+                    );
+                }
+            }
+        }
     }
 }
 
-// fn main() -> anyhow::Result<()> {
-//     dotenv::dotenv().expect("Failed to read .env file");
-
-//     let auth_cache = schedule_assistant::authentication::AuthenticationCache::new();
-//     let args: Vec<String> = env::args().collect();
-//     let start = if args.len() > 1 {
-//         Utc.datetime_from_str(&args[1], "%Y-%m-%d %H:%M:%S")? //"2021-03-22 00:00:00"
-//     } else {
-//         Utc::now()
-//     };
-//     let (start_of_week, end_of_week) = calculate_date_range(start); //< Calculate the date filter; Next week, starting from the following monday.
-//     let unscheduled = schedule_assistant::check_bookings(&auth_cache, start_of_week, end_of_week)?;
-
-//     println!(
-//         "There are {} unscheduled opportunities for the week {} to {}",
-//         unscheduled.len(),
-//         start_of_week,
-//         end_of_week
-//     );
-
-//     for opportunity in unscheduled {
-//         let member = &opportunity["member"];
-//         let member_name = json::attribute_from_value(&member, "name")
-//             .expect("Unable to extract client name from opportunity");
-//         let opportunity_id = opportunity["id"].as_u64().unwrap();
-//         println!("{} ({})", member_name, opportunity_id);
-//     }
-
-//     Ok(())
-// }
+// The number shown for the job in ServiceM8, or its uuid if it hasn't one.
+fn job_number<'a>(jobs: &'a [Job], uuid: &'a str) -> &'a str {
+    jobs.iter()
+        .find(|job| job.uuid == uuid && !job.generated_job_id.is_empty())
+        .map(|job| job.generated_job_id.as_str())
+        .unwrap_or(uuid)
+}
@@ -0,0 +1,305 @@
+use fake_server::{FakeServer, Fixtures};
+use schedule_assistant::links::LinkStore;
+use serde_json::json;
+
+#[test]
+fn lists_the_unscheduled_orders_within_the_window() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    let output = server
+        .command(env!("CARGO_BIN_EXE_check"))
+        .args(["2021-03-22 00:00:00", "7"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    // Jane Smith's job has an activity within the dates of the order.
+    assert!(!stdout.contains("(101)"), "{}", stdout);
+    assert!(stdout.contains("Acme Limited (102)"), "{}", stdout);
+    assert!(
+        stdout.contains("no client is named 'Acme Limited' (1 jobs)"),
+        "{}",
+        stdout
+    );
+    // A quotation rather than an order.
+    assert!(!stdout.contains("(103)"), "{}", stdout);
+    assert!(server.mutations().is_empty());
+}
+
+#[test]
+fn a_job_whose_client_is_missing_does_not_stop_the_check() {
+    let mut fixtures = Fixtures::examples().unwrap();
+    let mut orphan = fixtures.jobs[0].clone();
+    orphan["uuid"] = json!("j0000000-0000-0000-0000-000000000009");
+    orphan["company_uuid"] = json!("c0000000-0000-0000-0000-000000000009");
+    fixtures.jobs.insert(0, orphan);
+    let server = FakeServer::start(fixtures).unwrap();
+
+    let output = server
+        .command(env!("CARGO_BIN_EXE_check"))
+        .args(["2021-03-22 00:00:00", "7"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(!stdout.contains("(101)"), "{}", stdout);
+    assert!(stdout.contains("Acme Limited (102)"), "{}", stdout);
+}
+
+#[test]
+fn jobs_for_other_opportunities_are_summarised() {
+    let mut fixtures = Fixtures::examples().unwrap();
+    for n in 2..5 {
+        let mut job = fixtures.jobs[0].clone();
+        job["uuid"] = json!(format!("j0000000-0000-0000-0000-00000000000{}", n));
+        job["purchase_order_number"] = json!(format!("RMS#9{}", n));
+        fixtures.jobs.push(job);
+    }
+    let server = FakeServer::start(fixtures).unwrap();
+
+    let output = server
+        .command(env!("CARGO_BIN_EXE_check"))
+        .args(["2021-03-22 00:00:00", "7"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(!stdout.contains("is for opportunity"), "{}", stdout);
+    assert!(
+        stdout.contains(
+            "no client is named 'Acme Limited' (1 jobs) and no contacts match \
+             (1 jobs with contacts), 3 jobs are for other opportunities"
+        ),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn linked_orders_are_taken_as_scheduled() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    let mut links = LinkStore::open(server.links_file()).unwrap();
+    links
+        .add(102, "j0000000-0000-0000-0000-000000000001", "test")
+        .unwrap();
+
+    let output = server
+        .command(env!("CARGO_BIN_EXE_check"))
+        .args(["2021-03-22 00:00:00", "7"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(!stdout.contains("(102)"), "{}", stdout);
+}
+
+#[test]
+fn matches_are_written_back_to_both_records() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    let output = server
+        .command(env!("CARGO_BIN_EXE_check"))
+        .args(["--write-back", "2021-03-22 00:00:00", "7"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+
+    let mutations = server.mutations();
+    let paths: Vec<(&str, &str)> = mutations
+        .iter()
+        .map(|m| (m.method.as_str(), m.path.as_str()))
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            ("PUT", "/current/api/v1/opportunities/101"),
+            (
+                "POST",
+                "/servicem8/api_1.0/job/j0000000-0000-0000-0000-000000000001.json"
+            ),
+        ]
+    );
+    assert_eq!(
+        mutations[0].body["opportunity"]["custom_fields"]["servicem8_job_uuid"],
+        "j0000000-0000-0000-0000-000000000001"
+    );
+    assert_eq!(mutations[1].body["purchase_order_number"], "RMS#101");
+}
+
+// Runs a write back and returns the method and path of each mutation.
+fn write_back(fixtures: Fixtures) -> Vec<(String, String)> {
+    let server = FakeServer::start(fixtures).unwrap();
+    let output = server
+        .command(env!("CARGO_BIN_EXE_check"))
+        .args(["--write-back", "2021-03-22 00:00:00", "7"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    server
+        .mutations()
+        .into_iter()
+        .map(|m| (m.method, m.path))
+        .collect()
+}
+
+#[test]
+fn a_reference_on_one_side_is_written_back_to_the_other() {
+    // The job refers to the opportunity, but not the other way around.
+    let mut fixtures = Fixtures::examples().unwrap();
+    fixtures.jobs[0]["purchase_order_number"] = json!("RMS#101");
+    assert_eq!(
+        write_back(fixtures),
+        [(
+            String::from("PUT"),
+            String::from("/current/api/v1/opportunities/101")
+        )]
+    );
+
+    // And the other way around.
+    let mut fixtures = Fixtures::examples().unwrap();
+    fixtures.opportunities[0]["custom_fields"]["servicem8_job_uuid"] =
+        json!("j0000000-0000-0000-0000-000000000001");
+    assert_eq!(
+        write_back(fixtures),
+        [(
+            String::from("POST"),
+            String::from("/servicem8/api_1.0/job/j0000000-0000-0000-0000-000000000001.json")
+        )]
+    );
+
+    // Nothing is written once both refer to each other.
+    let mut fixtures = Fixtures::examples().unwrap();
+    fixtures.jobs[0]["purchase_order_number"] = json!("RMS#101");
+    fixtures.opportunities[0]["custom_fields"]["servicem8_job_uuid"] =
+        json!("j0000000-0000-0000-0000-000000000001");
+    assert!(write_back(fixtures).is_empty());
+}
+
+#[test]
+fn a_match_which_cannot_be_written_back_does_not_stop_the_others() {
+    // Jane has a second booking later in the week, and the customer's own
+    // purchase order number is on the first job.
+    let mut fixtures = Fixtures::examples().unwrap();
+    fixtures.jobs[0]["purchase_order_number"] = json!("PO-555");
+    let mut opportunity = fixtures.opportunities[0].clone();
+    opportunity["id"] = json!(104);
+    opportunity["starts_at"] = json!("2021-03-26T20:00:00.000Z");
+    opportunity["ends_at"] = json!("2021-03-28T04:00:00.000Z");
+    fixtures.opportunities.push(opportunity);
+    let mut job = fixtures.jobs[0].clone();
+    job["uuid"] = json!("j0000000-0000-0000-0000-000000000002");
+    job["generated_job_id"] = json!("1002");
+    job["purchase_order_number"] = json!("");
+    fixtures.jobs.push(job);
+    for (n, (start, end)) in [
+        ("2021-03-27 08:00:00", "2021-03-27 10:00:00"),
+        ("2021-03-27 15:00:00", "2021-03-27 17:00:00"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let mut activity = fixtures.job_activities[0].clone();
+        activity["uuid"] = json!(format!("a0000000-0000-0000-0000-00000000000{}", n + 3));
+        activity["job_uuid"] = json!("j0000000-0000-0000-0000-000000000002");
+        activity["start_date"] = json!(start);
+        activity["end_date"] = json!(end);
+        fixtures.job_activities.push(activity);
+    }
+    let server = FakeServer::start(fixtures).unwrap();
+
+    let output = server
+        .command(env!("CARGO_BIN_EXE_check"))
+        .args(["--write-back", "2021-03-22 00:00:00", "7"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(!output.status.success(), "{}", stdout);
+    assert!(stderr.contains("opportunity 101:"), "{}", stderr);
+    assert!(
+        stderr.contains("1 matches couldn't be written back"),
+        "{}",
+        stderr
+    );
+    let paths: Vec<(String, String)> = server
+        .mutations()
+        .into_iter()
+        .map(|m| (m.method, m.path))
+        .collect();
+    assert!(
+        paths.contains(&(
+            String::from("POST"),
+            String::from("/servicem8/api_1.0/job/j0000000-0000-0000-0000-000000000002.json")
+        )),
+        "{:?}",
+        paths
+    );
+}
+
+#[test]
+fn lists_activities_that_have_drifted() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    let output = server
+        .command(env!("CARGO_BIN_EXE_check"))
+        .args(["--reconcile", "2021-03-22 00:00:00", "7"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    // Jane Smith's delivery is after her event has started.
+    assert!(
+        stdout.contains("1 activities need to be rescheduled"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(
+            "job 1001: opportunity 101: the delivery on 2021-03-24 08:00 finishes after the event starts, move it to 2021-03-23"
+        ),
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn reconciles_a_job_which_has_drifted_too_far_to_match() {
+    // Both of Jane Smith's activities were left a week after her event.
+    let mut fixtures = Fixtures::examples().unwrap();
+    fixtures.job_activities[0]["start_date"] = json!("2021-03-31 08:00:00");
+    fixtures.job_activities[0]["end_date"] = json!("2021-03-31 10:00:00");
+    fixtures.job_activities[1]["start_date"] = json!("2021-04-01 15:00:00");
+    fixtures.job_activities[1]["end_date"] = json!("2021-04-01 17:00:00");
+    let server = FakeServer::start(fixtures).unwrap();
+
+    let output = server
+        .command(env!("CARGO_BIN_EXE_check"))
+        .args(["--reconcile", "2021-03-22 00:00:00", "7"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(
+        stdout.contains("2 activities need to be rescheduled"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("job 1001: opportunity 101: the delivery on 2021-03-31 08:00 finishes after the event starts"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains(
+            "job 1001: opportunity 101: the collection on 2021-04-01 15:00 is on the wrong day"
+        ),
+        "{}",
+        stdout
+    );
+}
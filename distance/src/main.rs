@@ -7,28 +7,26 @@ use std::path::Path;
 extern crate dotenv;
 
 fn main() {
-    dotenv::dotenv().expect("Failed to read .env file");
+    dotenv::dotenv().ok();
 
     // Convert the input file into geolocation coordinates
     let mut vec = Vec::new();
     if let Ok(lines) = read_lines("./places") {
-        for line in lines {
-            if let Ok(location) = line {
-                match geolocate(&location) {
-                    Ok(json) => {
-                        // Features is an array! Assume the first element is the one we want.
-                        let center = &json["features"][0]["center"];
-
-                        // Grab the coordinates and dump them into a Vec.
-                        vec.push(format!(
-                            "{}, {:?}",
-                            location,
-                            (center[0].as_f64().unwrap(), center[1].as_f64().unwrap())
-                        ));
-                    }
-                    _ => {
-                        vec.push(format!("{}, unknown", location));
-                    }
+        for location in lines.map_while(Result::ok) {
+            match geolocate(&location) {
+                Ok(json) => {
+                    // Features is an array! Assume the first element is the one we want.
+                    let center = &json["features"][0]["center"];
+
+                    // Grab the coordinates and dump them into a Vec.
+                    vec.push(format!(
+                        "{}, {:?}",
+                        location,
+                        (center[0].as_f64().unwrap(), center[1].as_f64().unwrap())
+                    ));
+                }
+                _ => {
+                    vec.push(format!("{}, unknown", location));
                 }
             }
         }
@@ -52,7 +50,7 @@ fn geolocate(location: &str) -> reqwest::Result<serde_json::Value> {
     //@todo: Need to url-encde the location.
     let url = format!(
         "{}/geocoding/v5/{}/{}.json?access_token={}",
-        "http://api.mapbox.com",
+        env::var("MAPBOX_API_URL").unwrap_or_else(|_| String::from("http://api.mapbox.com")),
         "mapbox.places",
         location,
         env::var("MAPBOX_ACCESS_TOKEN").expect("MAPBOX_ACCESS_TOKEN not found")
@@ -0,0 +1,101 @@
+use fake_server::{FakeServer, Fixtures};
+use serde_json::json;
+use std::path::Path;
+
+#[test]
+fn renders_an_email_for_each_job_in_the_week() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    let template = Path::new(env!("CARGO_MANIFEST_DIR")).join("../templates/template.hbs");
+    let output = server
+        .command(env!("CARGO_BIN_EXE_email"))
+        .env("EMAIL_TEMPLATE", template)
+        .args(["--dry-run", "2021-03-22"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Hey Jane!"), "{}", stdout);
+    assert!(
+        stdout.contains("1 Queen Street, Auckland 1010"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("not sending 1 emails"), "{}", stdout);
+}
+
+// A second client's job in the same week, with its own activity and contact.
+fn with_second_job(mut fixtures: Fixtures) -> Fixtures {
+    fixtures.companies.push(json!({
+        "uuid": "c0000000-0000-0000-0000-000000000002",
+        "active": 1,
+        "name": "Tom Brown",
+        "is_individual": 1,
+        "address": "2 King Street, Auckland 1010",
+    }));
+    fixtures.jobs.push(json!({
+        "uuid": "j0000000-0000-0000-0000-000000000002",
+        "active": 1,
+        "company_uuid": "c0000000-0000-0000-0000-000000000002",
+        "job_address": "2 King Street, Auckland 1010",
+        "status": "Work Order",
+        "date": "2021-03-26 00:00:00",
+    }));
+    fixtures.job_activities.push(json!({
+        "uuid": "a0000000-0000-0000-0000-000000000003",
+        "active": 1,
+        "job_uuid": "j0000000-0000-0000-0000-000000000002",
+        "start_date": "2021-03-26 08:00:00",
+        "end_date": "2021-03-26 10:00:00",
+        "activity_was_scheduled": 1,
+    }));
+    fixtures.job_contacts.push(json!({
+        "uuid": "p0000000-0000-0000-0000-000000000002",
+        "active": 1,
+        "job_uuid": "j0000000-0000-0000-0000-000000000002",
+        "first": "Tom",
+        "last": "Brown",
+        "email": "tom@example.com",
+        "type": "JOB",
+    }));
+    fixtures
+}
+
+#[test]
+fn looks_up_every_job_in_one_request_per_table() {
+    let server = FakeServer::start(with_second_job(Fixtures::examples().unwrap())).unwrap();
+    let template = Path::new(env!("CARGO_MANIFEST_DIR")).join("../templates/template.hbs");
+    let output = server
+        .command(env!("CARGO_BIN_EXE_email"))
+        .env("EMAIL_TEMPLATE", template)
+        .args(["--dry-run", "2021-03-22"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Hey Jane!"), "{}", stdout);
+    assert!(stdout.contains("Hey Tom!"), "{}", stdout);
+    assert!(stdout.contains("not sending 2 emails"), "{}", stdout);
+
+    let requests = server.requests();
+    let gets = |table: &str| {
+        let path = format!("/servicem8/api_1.0/{}.json", table);
+        requests
+            .iter()
+            .filter(|(method, request)| method == "GET" && *request == path)
+            .count()
+    };
+    assert_eq!(gets("job"), 1, "{:?}", requests);
+    assert_eq!(gets("company"), 1, "{:?}", requests);
+    assert_eq!(gets("jobcontact"), 1, "{:?}", requests);
+    // The week's activities and then all of them to join with the jobs.
+    assert_eq!(gets("jobactivity"), 2, "{:?}", requests);
+    assert!(
+        requests
+            .iter()
+            .all(|(_, path)| !path.starts_with("/current")),
+        "{:?}",
+        requests
+    );
+}
@@ -0,0 +1,650 @@
+// A stand-in for Current RMS, ServiceM8 and Mapbox which serves records from
+// fixtures, so that the tools can be run without touching the real accounts.
+//
+// Each service lives under its own prefix,
+//   /current/api/v1  the Current RMS API
+//   /current/web     the Current RMS web app, ie print_document.pdf
+//   /servicem8/api_1.0
+//   /mapbox
+// and every request which would change data is recorded as a Mutation.  Records
+// created or updated through ServiceM8 are applied so that they can be read back.
+
+mod query;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tiny_http::{Header, Request, Response, Server};
+use url::Url;
+
+// Credentials that the tools are given, the server only checks that some form
+// of authentication was supplied.
+pub static SUBDOMAIN: &str = "fake";
+pub static API_KEY: &str = "fake-api-key";
+pub static USERNAME: &str = "fake@example.com";
+pub static PASSWORD: &str = "fake-password";
+pub static MAPBOX_TOKEN: &str = "fake-mapbox-token";
+
+// Where Mapbox says everything is when there is no geocoding fixture.
+const DEFAULT_CENTER: (f64, f64) = (174.7633, -36.8485);
+
+// Travel time between any two points when there is no directions fixture.
+const DEFAULT_DURATION: f64 = 900.0;
+
+const DEFAULT_PER_PAGE: usize = 20;
+
+// Records to serve, each table is a list of JSON objects as the service would
+// return them.
+#[derive(Clone, Debug, Default)]
+pub struct Fixtures {
+    pub opportunities: Vec<Value>,
+    pub companies: Vec<Value>,
+    pub jobs: Vec<Value>,
+    pub job_activities: Vec<Value>,
+    pub job_contacts: Vec<Value>,
+    // Whole responses for the Mapbox endpoints.
+    pub geocoding: Option<Value>,
+    pub directions: Option<Value>,
+}
+
+impl Fixtures {
+    // Reads the fixtures from a directory holding opportunities.json,
+    // company.json, job.json, jobactivity.json, jobcontact.json, geocoding.json
+    // and directions.json.  Any of these can be left out.
+    pub fn load(dir: &Path) -> anyhow::Result<Fixtures> {
+        Ok(Fixtures {
+            opportunities: load_list(&dir.join("opportunities.json"))?,
+            companies: load_list(&dir.join("company.json"))?,
+            jobs: load_list(&dir.join("job.json"))?,
+            job_activities: load_list(&dir.join("jobactivity.json"))?,
+            job_contacts: load_list(&dir.join("jobcontact.json"))?,
+            geocoding: load_value(&dir.join("geocoding.json"))?,
+            directions: load_value(&dir.join("directions.json"))?,
+        })
+    }
+
+    // The example records in fake_server/fixtures.
+    pub fn examples() -> anyhow::Result<Fixtures> {
+        Fixtures::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures"))
+    }
+
+    fn servicem8_table(&self, name: &str) -> Option<&Vec<Value>> {
+        match name {
+            "company" => Some(&self.companies),
+            "job" => Some(&self.jobs),
+            "jobactivity" => Some(&self.job_activities),
+            "jobcontact" => Some(&self.job_contacts),
+            _ => None,
+        }
+    }
+
+    fn servicem8_table_mut(&mut self, name: &str) -> Option<&mut Vec<Value>> {
+        match name {
+            "company" => Some(&mut self.companies),
+            "job" => Some(&mut self.jobs),
+            "jobactivity" => Some(&mut self.job_activities),
+            "jobcontact" => Some(&mut self.job_contacts),
+            _ => None,
+        }
+    }
+}
+
+fn load_value(path: &Path) -> anyhow::Result<Option<Value>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(path)?;
+    let value = serde_json::from_str(&contents)
+        .map_err(|e| anyhow::anyhow!("unable to parse {}: {}", path.display(), e))?;
+    Ok(Some(value))
+}
+
+fn load_list(path: &Path) -> anyhow::Result<Vec<Value>> {
+    match load_value(path)? {
+        Some(Value::Array(list)) => Ok(list),
+        Some(_) => anyhow::bail!("{} should contain a list of records", path.display()),
+        None => Ok(Vec::new()),
+    }
+}
+
+// A request that would have changed data in one of the services.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Mutation {
+    pub method: String,
+    pub path: String,
+    pub body: Value,
+}
+
+// An error to answer requests with in place of handling them, for testing how
+// the tools cope with the services failing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    // How many requests fail before the path starts working again.
+    pub times: usize,
+    pub retry_after: Option<String>,
+}
+
+impl Failure {
+    pub fn new(method: &str, path: &str, status: u16) -> Self {
+        Failure {
+            method: method.to_string(),
+            path: path.to_string(),
+            status,
+            times: usize::MAX,
+            retry_after: None,
+        }
+    }
+
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = times;
+        self
+    }
+
+    pub fn retry_after(mut self, seconds: &str) -> Self {
+        self.retry_after = Some(seconds.to_string());
+        self
+    }
+}
+
+struct State {
+    fixtures: Fixtures,
+    mutations: Vec<Mutation>,
+    failures: Vec<Failure>,
+    // The method and path of every request received.
+    requests: Vec<(String, String)>,
+    // Mutations are also appended here, one JSON object per line.
+    journal: Option<File>,
+}
+
+pub struct FakeServer {
+    server: Arc<Server>,
+    state: Arc<Mutex<State>>,
+    thread: Option<JoinHandle<()>>,
+    url: String,
+    // Somewhere for the tools to keep opportunity links that is removed along
+    // with the server.
+    links_file: PathBuf,
+}
+
+impl FakeServer {
+    // Starts serving the fixtures on a free port.
+    pub fn start(fixtures: Fixtures) -> anyhow::Result<FakeServer> {
+        Self::start_on(0, fixtures, None)
+    }
+
+    pub fn start_on(
+        port: u16,
+        fixtures: Fixtures,
+        journal: Option<&Path>,
+    ) -> anyhow::Result<FakeServer> {
+        let journal = match journal {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        let server = Server::http(("127.0.0.1", port)).map_err(|e| anyhow::anyhow!(e))?;
+        let port = server
+            .server_addr()
+            .to_ip()
+            .map(|addr| addr.port())
+            .ok_or_else(|| anyhow::anyhow!("the server isn't listening on a TCP port"))?;
+
+        let server = Arc::new(server);
+        let state = Arc::new(Mutex::new(State {
+            fixtures,
+            mutations: Vec::new(),
+            failures: Vec::new(),
+            requests: Vec::new(),
+            journal,
+        }));
+
+        let thread = {
+            let server = Arc::clone(&server);
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle(&state, request);
+                }
+            })
+        };
+
+        Ok(FakeServer {
+            server,
+            state,
+            thread: Some(thread),
+            url: format!("http://127.0.0.1:{}", port),
+            links_file: std::env::temp_dir().join(format!("fake_server_{}_links.json", port)),
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn links_file(&self) -> &Path {
+        &self.links_file
+    }
+
+    // The environment which points the tools at this server.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            (
+                "CURRENT_RMS_API_URL",
+                format!("{}/current/api/v1", self.url),
+            ),
+            ("CURRENT_RMS_WEB_URL", format!("{}/current/web", self.url)),
+            (
+                "SERVICEM8_API_URL",
+                format!("{}/servicem8/api_1.0", self.url),
+            ),
+            ("MAPBOX_API_URL", format!("{}/mapbox", self.url)),
+            ("CURRENT_AUTH_METHOD", String::from("api_key")),
+            ("CURRENT_DOMAIN_NAME", SUBDOMAIN.to_string()),
+            ("CURRENT_API_KEY", API_KEY.to_string()),
+            ("SERVICEM8_USERNAME", USERNAME.to_string()),
+            ("SERVICEM8_PASSWORD", PASSWORD.to_string()),
+            ("MAPBOX_ACCESS_TOKEN", MAPBOX_TOKEN.to_string()),
+            ("LINKS_FILE", self.links_file.display().to_string()),
+        ]
+    }
+
+    // A command for one of the tools, run from the temporary directory so that
+    // no .env file is picked up and with the environment set to use this server.
+    pub fn command<P: AsRef<Path>>(&self, program: P) -> Command {
+        let mut command = Command::new(program.as_ref());
+        command.current_dir(std::env::temp_dir());
+        command.envs(self.env());
+        command
+    }
+
+    // Every mutation received so far, in the order they arrived.
+    pub fn mutations(&self) -> Vec<Mutation> {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .mutations
+            .clone()
+    }
+
+    // Every request received so far as (method, path), including those that
+    // were failed.
+    pub fn requests(&self) -> Vec<(String, String)> {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .requests
+            .clone()
+    }
+
+    pub fn fail(&self, failure: Failure) {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .failures
+            .push(failure);
+    }
+
+    // Blocks until the server stops, which is never for the binary.
+    pub fn wait(mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Runs one of the tools, failing the test with everything it printed unless it
+// succeeds, and returns what it wrote to stdout.
+pub fn run(command: &mut Command) -> String {
+    let output = command.output().expect("the tool runs");
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    assert!(
+        output.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    stdout
+}
+
+impl Drop for FakeServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = fs::remove_file(&self.links_file);
+    }
+}
+
+fn handle(state: &Mutex<State>, mut request: Request) {
+    let method = request.method().to_string();
+    let url = match Url::parse(&format!("http://localhost{}", request.url())) {
+        Ok(url) => url,
+        Err(_) => {
+            let _ = request.respond(Response::empty(400));
+            return;
+        }
+    };
+    println!("{} {}", method, request.url());
+
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+    let body = if body.trim().is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_str(&body).unwrap_or(Value::String(body))
+    };
+
+    let authenticated = request
+        .headers()
+        .iter()
+        .any(|header| header.field.equiv("Authorization") || header.field.equiv("X-AUTH-TOKEN"));
+    let segments: Vec<String> = url
+        .path_segments()
+        .map(|segments| segments.map(String::from).collect())
+        .unwrap_or_default();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+
+    let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+    state
+        .requests
+        .push((method.clone(), url.path().to_string()));
+    if let Some(reply) = injected_failure(&mut state, &method, url.path()) {
+        drop(state);
+        let _ = request.respond(reply.into_response());
+        return;
+    }
+    let reply = match segments.as_slice() {
+        ["mapbox", rest @ ..] => mapbox(&state.fixtures, rest),
+        _ if !authenticated => Reply::status(401),
+        ["current", "api", "v1", rest @ ..] => {
+            current_rms(&mut state, &method, rest, &params, &url, &body)
+        }
+        ["current", "web", "opportunities", id, "print_document.pdf"] => Reply::pdf(id),
+        ["servicem8", "api_1.0", rest @ ..] => {
+            servicem8(&mut state, &method, rest, &params, &url, &body)
+        }
+        _ => Reply::status(404),
+    };
+    drop(state);
+
+    let _ = request.respond(reply.into_response());
+}
+
+fn injected_failure(state: &mut State, method: &str, path: &str) -> Option<Reply> {
+    let failure = state
+        .failures
+        .iter_mut()
+        .find(|failure| failure.times > 0 && failure.method == method && failure.path == path)?;
+    failure.times -= 1;
+    let mut reply = Reply::status(failure.status);
+    if let Some(seconds) = &failure.retry_after {
+        reply
+            .headers
+            .push((String::from("Retry-After"), seconds.clone()));
+    }
+    Some(reply)
+}
+
+struct Reply {
+    status: u16,
+    body: Vec<u8>,
+    headers: Vec<(String, String)>,
+}
+
+impl Reply {
+    fn json(value: Value) -> Reply {
+        Reply {
+            status: 200,
+            body: value.to_string().into_bytes(),
+            headers: vec![(
+                String::from("Content-Type"),
+                String::from("application/json"),
+            )],
+        }
+    }
+
+    fn status(status: u16) -> Reply {
+        Reply {
+            status,
+            body: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    fn pdf(opportunity_id: &str) -> Reply {
+        Reply {
+            status: 200,
+            body: format!("%PDF-1.4\n% picking list for {}\n%%EOF\n", opportunity_id).into_bytes(),
+            headers: vec![
+                (
+                    String::from("Content-Type"),
+                    String::from("application/pdf"),
+                ),
+                (
+                    String::from("Content-Disposition"),
+                    format!(
+                        "attachment; filename=\"picking_list_{}.pdf\"",
+                        opportunity_id
+                    ),
+                ),
+            ],
+        }
+    }
+
+    fn into_response(self) -> Response<io::Cursor<Vec<u8>>> {
+        let mut response = Response::from_data(self.body).with_status_code(self.status);
+        for (field, value) in self.headers {
+            if let Ok(header) = Header::from_bytes(field.as_bytes(), value.as_bytes()) {
+                response.add_header(header);
+            }
+        }
+        response
+    }
+}
+
+fn record(state: &mut State, method: &str, url: &Url, body: &Value) {
+    let mutation = Mutation {
+        method: method.to_string(),
+        path: url.path().to_string(),
+        body: body.clone(),
+    };
+    if let Some(journal) = state.journal.as_mut() {
+        if let Ok(line) = serde_json::to_string(&mutation) {
+            let _ = writeln!(journal, "{}", line);
+        }
+    }
+    state.mutations.push(mutation);
+}
+
+fn current_rms(
+    state: &mut State,
+    method: &str,
+    segments: &[&str],
+    params: &[(String, String)],
+    url: &Url,
+    body: &Value,
+) -> Reply {
+    let find = |state: &State, id: &str| {
+        state
+            .fixtures
+            .opportunities
+            .iter()
+            .find(|opportunity| opportunity["id"].as_u64() == id.parse().ok())
+            .cloned()
+    };
+
+    match (method, segments) {
+        ("GET", ["opportunities"]) => {
+            let matching: Vec<&Value> = state
+                .fixtures
+                .opportunities
+                .iter()
+                .filter(|opportunity| query::ransack_matches(opportunity, params))
+                .collect();
+            let param = |name: &str| {
+                params
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .and_then(|(_, value)| value.parse::<usize>().ok())
+            };
+            let page = param("page").unwrap_or(1).max(1);
+            let per_page = param("per_page").unwrap_or(DEFAULT_PER_PAGE).max(1);
+            let records: Vec<&Value> = matching
+                .iter()
+                .skip((page - 1) * per_page)
+                .take(per_page)
+                .cloned()
+                .collect();
+            Reply::json(json!({
+                "opportunities": records,
+                "meta": {
+                    "total_row_count": matching.len(),
+                    "row_count": records.len(),
+                    "page": page,
+                    "per_page": per_page,
+                }
+            }))
+        }
+        ("GET", ["opportunities", id]) => match find(state, id) {
+            Some(opportunity) => Reply::json(json!({ "opportunity": opportunity })),
+            None => Reply::status(404),
+        },
+        ("POST", ["opportunities"]) => {
+            record(state, method, url, body);
+            let mut opportunity = body["opportunity"].clone();
+            let id = state.fixtures.opportunities.len() as u64 + 1000;
+            opportunity["id"] = json!(id);
+            Reply::json(json!({ "opportunity": opportunity }))
+        }
+        ("PUT", ["opportunities", id]) | ("DELETE", ["opportunities", id]) => {
+            match find(state, id) {
+                Some(opportunity) => {
+                    record(state, method, url, body);
+                    Reply::json(json!({ "opportunity": opportunity }))
+                }
+                None => Reply::status(404),
+            }
+        }
+        // Actions such as mark_as_dead.
+        ("POST", ["opportunities", id, _action]) => match find(state, id) {
+            Some(opportunity) => {
+                record(state, method, url, body);
+                Reply::json(json!({ "opportunity": opportunity }))
+            }
+            None => Reply::status(404),
+        },
+        _ => Reply::status(404),
+    }
+}
+
+fn servicem8(
+    state: &mut State,
+    method: &str,
+    segments: &[&str],
+    params: &[(String, String)],
+    url: &Url,
+    body: &Value,
+) -> Reply {
+    let (name, uuid) = match segments {
+        [table] => (table.trim_end_matches(".json"), None),
+        [table, uuid] => (*table, Some(uuid.trim_end_matches(".json"))),
+        _ => return Reply::status(404),
+    };
+    let table = match state.fixtures.servicem8_table(name) {
+        Some(table) => table,
+        None => return Reply::status(404),
+    };
+
+    match (method, uuid) {
+        ("GET", None) => {
+            let filter = params
+                .iter()
+                .find(|(key, _)| key == "$filter")
+                .map(|(_, value)| value.clone());
+            let records: Vec<Value> = table
+                .iter()
+                .filter(|record| match &filter {
+                    Some(filter) => query::odata_matches(record, filter),
+                    None => true,
+                })
+                .cloned()
+                .collect();
+            Reply::json(Value::Array(records))
+        }
+        ("GET", Some(uuid)) => match table.iter().find(|record| record["uuid"] == uuid) {
+            Some(record) => Reply::json(record.clone()),
+            None => Reply::status(404),
+        },
+        ("POST", None) => {
+            record(state, method, url, body);
+            let mut created = body.clone();
+            let uuid = match created["uuid"].as_str() {
+                Some(uuid) => uuid.to_string(),
+                None => uuid::Uuid::new_v4().to_string(),
+            };
+            created["uuid"] = json!(uuid);
+            if created.get("active").is_none() {
+                created["active"] = json!(1);
+            }
+            if let Some(table) = state.fixtures.servicem8_table_mut(name) {
+                table.push(created);
+            }
+            let mut reply = Reply::json(json!({ "errorCode": 0, "message": "OK" }));
+            reply.headers.push((String::from("x-record-uuid"), uuid));
+            reply
+        }
+        ("POST", Some(uuid)) | ("DELETE", Some(uuid)) => {
+            record(state, method, url, body);
+            let table = match state.fixtures.servicem8_table_mut(name) {
+                Some(table) => table,
+                None => return Reply::status(404),
+            };
+            let existing = match table.iter_mut().find(|record| record["uuid"] == uuid) {
+                Some(existing) => existing,
+                None => return Reply::status(404),
+            };
+            if method == "DELETE" {
+                existing["active"] = json!(0);
+            } else if let (Some(existing), Some(fields)) =
+                (existing.as_object_mut(), body.as_object())
+            {
+                for (key, value) in fields {
+                    existing.insert(key.clone(), value.clone());
+                }
+            }
+            Reply::json(json!({ "errorCode": 0, "message": "OK" }))
+        }
+        _ => Reply::status(405),
+    }
+}
+
+fn mapbox(fixtures: &Fixtures, segments: &[&str]) -> Reply {
+    match segments {
+        ["geocoding", "v5", _, _location] => {
+            Reply::json(fixtures.geocoding.clone().unwrap_or_else(|| {
+                json!({
+                    "type": "FeatureCollection",
+                    "features": [{ "center": [DEFAULT_CENTER.0, DEFAULT_CENTER.1] }]
+                })
+            }))
+        }
+        ["directions", "v5", _, _, _coordinates] => {
+            Reply::json(fixtures.directions.clone().unwrap_or_else(|| {
+                json!({
+                    "code": "Ok",
+                    "routes": [{ "duration": DEFAULT_DURATION, "distance": 10000.0 }]
+                })
+            }))
+        }
+        _ => Reply::status(404),
+    }
+}
@@ -0,0 +1,47 @@
+// name: fake_server
+// type: command line application
+// desc: serves fixture backed versions of the Current RMS, ServiceM8 and
+//       Mapbox endpoints that the tools use, so they can be run locally.
+//
+// usage: fake_server [--port PORT] [--fixtures DIR] [--journal FILE]
+//
+// The fixtures default to those in fake_server/fixtures, and mutations are
+// appended to the journal as JSON lines when one is given.
+
+use fake_server::{FakeServer, Fixtures};
+use std::env;
+use std::path::PathBuf;
+
+fn main() -> anyhow::Result<()> {
+    let mut port = 8000;
+    let mut fixture_dir: Option<PathBuf> = None;
+    let mut journal: Option<PathBuf> = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("{} expects a value", arg))
+        };
+        match arg.as_str() {
+            "--port" => port = value()?.parse()?,
+            "--fixtures" => fixture_dir = Some(PathBuf::from(value()?)),
+            "--journal" => journal = Some(PathBuf::from(value()?)),
+            _ => anyhow::bail!("unknown argument '{}'", arg),
+        }
+    }
+
+    let fixtures = match &fixture_dir {
+        Some(dir) => Fixtures::load(dir)?,
+        None => Fixtures::examples()?,
+    };
+    let server = FakeServer::start_on(port, fixtures, journal.as_deref())?;
+    println!("Serving on {}", server.url());
+    println!("Point the tools at it with:");
+    for (name, value) in server.env() {
+        println!("export {}={}", name, value);
+    }
+
+    server.wait();
+    Ok(())
+}
@@ -0,0 +1,131 @@
+// Just enough of the filtering that Current RMS (ransack) and ServiceM8 (OData
+// $filter) perform for the tools to see the same records that they would from
+// the real services.
+
+use serde_json::Value;
+use std::cmp::Ordering;
+
+// The text of a scalar, so that 3 and "3" compare as equal.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+// Numbers are compared numerically and anything else as text, which works for
+// the ISO formatted dates used by both services.
+fn compare(value: &Value, expected: &str) -> Option<Ordering> {
+    let actual = scalar(value)?;
+    match (actual.parse::<f64>(), expected.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b),
+        _ => Some(actual.as_str().cmp(expected)),
+    }
+}
+
+// Matches a record against the q[attribute_predicate] parameters, predicates
+// that aren't understood are ignored.
+pub fn ransack_matches(record: &Value, params: &[(String, String)]) -> bool {
+    let mut any_of: Vec<(&str, Vec<&str>)> = Vec::new();
+    for (key, value) in params {
+        let condition = match key.strip_prefix("q[").and_then(|k| k.strip_suffix(']')) {
+            Some(condition) => condition,
+            None => continue,
+        };
+
+        if let Some(attribute) = condition.strip_suffix("_in][") {
+            match any_of.iter_mut().find(|(a, _)| *a == attribute) {
+                Some((_, values)) => values.push(value),
+                None => any_of.push((attribute, vec![value])),
+            }
+            continue;
+        }
+
+        let (attribute, predicate) = match condition.rfind('_') {
+            Some(i) => (&condition[..i], &condition[i + 1..]),
+            None => continue,
+        };
+        let ordering = compare(&record[attribute], value);
+        let matches = match predicate {
+            "eq" => ordering == Some(Ordering::Equal),
+            "lt" => ordering == Some(Ordering::Less),
+            "gt" => ordering == Some(Ordering::Greater),
+            "lteq" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            "gteq" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            _ => continue,
+        };
+        if !matches {
+            return false;
+        }
+    }
+
+    any_of.iter().all(|(attribute, values)| {
+        values
+            .iter()
+            .any(|value| compare(&record[*attribute], value) == Some(Ordering::Equal))
+    })
+}
+
+// A single "field operator value" clause of a $filter expression.
+struct Clause {
+    field: String,
+    operator: String,
+    value: String,
+}
+
+fn parse_clause(clause: &str) -> Option<Clause> {
+    let mut parts = clause.trim().splitn(3, ' ');
+    let field = parts.next()?.to_string();
+    let operator = parts.next()?.to_string();
+    let value = parts.next()?.trim();
+    let value = match value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        Some(text) => text.replace("''", "'"),
+        None => value.to_string(),
+    };
+    Some(Clause {
+        field,
+        operator,
+        value,
+    })
+}
+
+// Splits on " and " outside of quoted strings.
+fn split_clauses(expression: &str) -> Vec<String> {
+    let mut clauses = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut rest = expression;
+    while let Some(c) = rest.chars().next() {
+        if c == '\'' {
+            quoted = !quoted;
+        }
+        if !quoted && rest.starts_with(" and ") {
+            clauses.push(std::mem::take(&mut current));
+            rest = &rest[5..];
+            continue;
+        }
+        current.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    clauses.push(current);
+    clauses
+}
+
+pub fn odata_matches(record: &Value, expression: &str) -> bool {
+    split_clauses(expression).iter().all(|clause| {
+        let clause = match parse_clause(clause) {
+            Some(clause) => clause,
+            None => return true,
+        };
+        let ordering = compare(&record[clause.field.as_str()], &clause.value);
+        match clause.operator.as_str() {
+            "eq" => ordering == Some(Ordering::Equal),
+            "ne" => ordering != Some(Ordering::Equal),
+            "gt" => ordering == Some(Ordering::Greater),
+            "lt" => ordering == Some(Ordering::Less),
+            _ => true,
+        }
+    })
+}
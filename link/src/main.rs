@@ -0,0 +1,101 @@
+// ---------------------------------------------------------------------
+// name: Link
+// type: Command line application.
+// desc: Records which servicem8 job belongs to a current-rms opportunity
+//       when the tools can't work it out for themselves.
+// ---------------------------------------------------------------------
+//
+// usage:
+//   link add OPPORTUNITY JOB_UUID [--by NAME]
+//   link remove OPPORTUNITY
+//   link list
+
+use schedule_assistant::links::{Link, LinkStore};
+use std::env;
+
+static USAGE: &str =
+    "usage: link add OPPORTUNITY JOB_UUID [--by NAME] | link remove OPPORTUNITY | link list";
+
+enum Command {
+    Add {
+        opportunity_id: u64,
+        job_uuid: String,
+        linked_by: String,
+    },
+    Remove {
+        opportunity_id: u64,
+    },
+    List,
+}
+
+fn parse_command_line() -> anyhow::Result<Command> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let opportunity = |id: &str| {
+        id.parse::<u64>()
+            .map_err(|_| anyhow::anyhow!("'{}' isn't an opportunity number", id))
+    };
+
+    match args.as_slice() {
+        ["add", id, job_uuid, rest @ ..] => {
+            let linked_by = match rest {
+                [] => default_user(),
+                ["--by", name] => name.to_string(),
+                _ => anyhow::bail!(USAGE),
+            };
+            Ok(Command::Add {
+                opportunity_id: opportunity(id)?,
+                job_uuid: job_uuid.to_string(),
+                linked_by,
+            })
+        }
+        ["remove", id] => Ok(Command::Remove {
+            opportunity_id: opportunity(id)?,
+        }),
+        ["list"] => Ok(Command::List),
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+// Whoever is logged in, for recording who made the link.
+fn default_user() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| String::from("unknown"))
+}
+
+fn print_link(link: &Link) {
+    println!(
+        "{} -> {} (linked by {} on {})",
+        link.opportunity_id,
+        link.job_uuid,
+        link.linked_by,
+        link.linked_at.format("%Y-%m-%d %H:%M")
+    );
+}
+
+fn main() -> anyhow::Result<()> {
+    dotenv::dotenv().ok();
+
+    let command = parse_command_line()?;
+    let mut links = LinkStore::from_env()?;
+    match command {
+        Command::Add {
+            opportunity_id,
+            job_uuid,
+            linked_by,
+        } => print_link(links.add(opportunity_id, &job_uuid, &linked_by)?),
+        Command::Remove { opportunity_id } => match links.remove(opportunity_id)? {
+            Some(link) => println!("Removed {} -> {}", link.opportunity_id, link.job_uuid),
+            None => println!("Opportunity {} isn't linked", opportunity_id),
+        },
+        Command::List => {
+            if links.links().is_empty() {
+                println!("There are no links");
+            }
+            links.links().iter().for_each(print_link);
+        }
+    }
+
+    Ok(())
+}
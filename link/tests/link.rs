@@ -0,0 +1,35 @@
+use std::process::Command;
+
+fn link(links_file: &std::path::Path, args: &[&str]) -> String {
+    fake_server::run(
+        Command::new(env!("CARGO_BIN_EXE_link"))
+            .current_dir(std::env::temp_dir())
+            .env("LINKS_FILE", links_file)
+            .args(args),
+    )
+}
+
+#[test]
+fn adds_lists_and_removes_links() {
+    let links_file = std::env::temp_dir().join(format!("link_test_{}.json", std::process::id()));
+
+    let added = link(&links_file, &["add", "1234", "abc-uuid", "--by", "jared"]);
+    assert!(
+        added.contains("1234 -> abc-uuid (linked by jared"),
+        "{}",
+        added
+    );
+
+    // Linking again replaces the job rather than adding a second link.
+    link(&links_file, &["add", "1234", "def-uuid", "--by", "jared"]);
+    let listed = link(&links_file, &["list"]);
+    assert!(listed.contains("1234 -> def-uuid"), "{}", listed);
+    assert!(!listed.contains("abc-uuid"), "{}", listed);
+
+    let removed = link(&links_file, &["remove", "1234"]);
+    assert!(removed.contains("Removed 1234 -> def-uuid"), "{}", removed);
+    let listed = link(&links_file, &["list"]);
+    assert!(listed.contains("There are no links"), "{}", listed);
+
+    std::fs::remove_file(&links_file).unwrap();
+}
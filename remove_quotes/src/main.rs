@@ -1,13 +1,20 @@
-use anyhow;
-use schedule_assistant;
 use chrono::Utc;
 
 fn main() -> anyhow::Result<()> {
-    dotenv::dotenv().expect("Failed to read .env file");
+    dotenv::dotenv().ok();
 
     let now = Utc::now();
-    let auth_cache = schedule_assistant::authentication::AuthenticationCache::new();
-    schedule_assistant::remove_expired_quotes( &auth_cache, now );
+    let auth_cache = schedule_assistant::authentication::AuthenticationCache::new()?;
+    let failures = schedule_assistant::remove_expired_quotes(&auth_cache, now)?;
 
+    if !failures.is_empty() {
+        for (opportunity_id, e) in &failures {
+            eprintln!("quote {}: {}", opportunity_id, e);
+        }
+        anyhow::bail!(
+            "{} expired quotes couldn't be marked as lost",
+            failures.len()
+        );
+    }
     Ok(())
 }
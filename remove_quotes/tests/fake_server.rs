@@ -0,0 +1,152 @@
+use fake_server::{Failure, FakeServer, Fixtures};
+use std::io::{BufRead, BufReader};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+static MARK_AS_DEAD: &str = "/current/api/v1/opportunities/103/mark_as_dead";
+static OPPORTUNITIES: &str = "/current/api/v1/opportunities";
+
+// How many times the method and path were requested.
+fn requested(server: &FakeServer, method: &str, path: &str) -> usize {
+    server
+        .requests()
+        .iter()
+        .filter(|request| request.0 == method && request.1 == path)
+        .count()
+}
+
+#[test]
+fn marks_only_the_expired_quotes_as_lost() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    let output = server
+        .command(env!("CARGO_BIN_EXE_remove_quotes"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+
+    let mutations = server.mutations();
+    assert_eq!(mutations.len(), 1, "{:?}", mutations);
+    assert_eq!(mutations[0].method, "POST");
+    assert_eq!(
+        mutations[0].path,
+        "/current/api/v1/opportunities/103/mark_as_dead"
+    );
+}
+
+#[test]
+fn carries_on_past_a_quote_that_fails() {
+    let mut fixtures = Fixtures::examples().unwrap();
+    let mut second = fixtures.opportunities[2].clone();
+    second["id"] = serde_json::json!(104);
+    fixtures.opportunities.push(second);
+    let server = FakeServer::start(fixtures).unwrap();
+    server.fail(Failure::new("POST", MARK_AS_DEAD, 422));
+
+    let output = server
+        .command(env!("CARGO_BIN_EXE_remove_quotes"))
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("quote 103:"), "{}", stderr);
+    assert!(
+        stderr.contains("1 expired quotes couldn't be marked as lost"),
+        "{}",
+        stderr
+    );
+
+    // The quote after the one that failed was still marked.
+    let paths: Vec<String> = server.mutations().into_iter().map(|m| m.path).collect();
+    assert_eq!(paths, ["/current/api/v1/opportunities/104/mark_as_dead"]);
+}
+
+#[test]
+fn retries_a_rate_limited_post() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    server.fail(
+        Failure::new("POST", MARK_AS_DEAD, 429)
+            .times(1)
+            .retry_after("0"),
+    );
+
+    let output = server
+        .command(env!("CARGO_BIN_EXE_remove_quotes"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert_eq!(requested(&server, "POST", MARK_AS_DEAD), 2);
+    assert_eq!(server.mutations().len(), 1);
+}
+
+#[test]
+fn retries_a_get_which_fails_on_the_server() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    server.fail(Failure::new("GET", OPPORTUNITIES, 503).times(2));
+
+    let output = server
+        .command(env!("CARGO_BIN_EXE_remove_quotes"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert_eq!(requested(&server, "GET", OPPORTUNITIES), 3);
+    assert_eq!(server.mutations().len(), 1);
+}
+
+#[test]
+fn does_not_retry_a_post_which_fails_on_the_server() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    server.fail(Failure::new("POST", MARK_AS_DEAD, 500).times(1));
+
+    let output = server
+        .command(env!("CARGO_BIN_EXE_remove_quotes"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    // It may have been applied before failing, so isn't sent again.
+    assert_eq!(requested(&server, "POST", MARK_AS_DEAD), 1);
+}
+
+#[test]
+fn waits_no_longer_than_the_longest_retry_after() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    server.fail(Failure::new("GET", OPPORTUNITIES, 429).retry_after("86400"));
+
+    // The delay is reported before waiting, so there is no need to sit it out.
+    let mut child = server
+        .command(env!("CARGO_BIN_EXE_remove_quotes"))
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let stdout = child.stdout.take().unwrap();
+    let retrying = BufReader::new(stdout)
+        .lines()
+        .map_while(Result::ok)
+        .find(|line| line.starts_with("Retrying in"));
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert_eq!(retrying.as_deref(), Some("Retrying in 120.0s"));
+}
+
+#[test]
+fn rate_limits_a_service_at_a_configured_url() {
+    let mut fixtures = Fixtures::examples().unwrap();
+    let mut second = fixtures.opportunities[2].clone();
+    second["id"] = serde_json::json!(104);
+    fixtures.opportunities.push(second);
+    let server = FakeServer::start(fixtures).unwrap();
+
+    // Current RMS takes a request a second, so the list and two quotes can't
+    // be done within two seconds even though the server isn't Current RMS.
+    let started = Instant::now();
+    let output = server
+        .command(env!("CARGO_BIN_EXE_remove_quotes"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(server.mutations().len(), 2);
+    assert!(started.elapsed() >= Duration::from_secs(2));
+}
@@ -6,7 +6,7 @@ use url::Url;
 
 pub fn get(url: &str) -> reqwest::Result<serde_json::Value> {
     let client = Client::new();
-    let encoded_url = Url::parse(&url).unwrap();
+    let encoded_url = Url::parse(url).unwrap();
     let response = client
         .get(encoded_url)
         // .header("X-SUBDOMAIN", domain)
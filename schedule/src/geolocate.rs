@@ -5,12 +5,19 @@ use std::env;
 
 use crate::comms;
 
-static MAPBOX_URL: &str = "http://api.mapbox.com";
+static DEFAULT_MAPBOX_URL: &str = "http://api.mapbox.com";
+
+// Mapbox can be swapped for a stand-in by setting MAPBOX_API_URL.
+fn mapbox_url() -> String {
+    env::var("MAPBOX_API_URL")
+        .map(|url| url.trim_end_matches('/').to_string())
+        .unwrap_or_else(|_| DEFAULT_MAPBOX_URL.to_string())
+}
 
 fn geocoding(location: &str) -> reqwest::Result<serde_json::Value> {
     let url = format!(
         "{}/geocoding/v5/{}/{}.json?access_token={}",
-        MAPBOX_URL,
+        mapbox_url(),
         "mapbox.places",
         location,
         env::var("MAPBOX_ACCESS_TOKEN").expect("MAPBOX_ACCESS_TOKEN not found")
@@ -20,7 +27,7 @@ fn geocoding(location: &str) -> reqwest::Result<serde_json::Value> {
 
 pub fn locate(location: &str) -> Option<(f64, f64)> {
     // Attempt to find the locaiton supplied.
-    let json = geocoding(&location).ok()?;
+    let json = geocoding(location).ok()?;
 
     // Features is an array! Assume the first element is the one we want.
     let center = &json["features"][0]["center"];
@@ -36,7 +43,7 @@ pub fn directions(coordinates: &[(f64, f64)]) -> reqwest::Result<serde_json::Val
         .join(";");
     let url = format!(
         "{}/directions/v5/{}/{}/{}?alternatives=false&steps=false&access_token={}",
-        MAPBOX_URL,
+        mapbox_url(),
         "mapbox",
         "driving",
         coords,
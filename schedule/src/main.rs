@@ -5,12 +5,12 @@
 // 2. Need to store the any timing windows required by the client somewhere, somehow.  This needs to be independant of servicem8 and
 //      if a window of an extended period the algorithm only needs to ensure that the found path falls somewhere in that time.
 
-use anyhow;
 use chrono::prelude::*;
 use chrono::Duration;
 use std::cmp;
 
-use schedule_assistant::{current_rms};
+use schedule_assistant::current_rms;
+use schedule_assistant::models::{Opportunity, STATE_ORDER};
 
 mod comms;
 mod geolocate;
@@ -18,17 +18,10 @@ mod geolocate;
 static HOME_ADDRESS: &str = "44b Henderson Valley Road, Henderson, Auckland";
 static HOME_PT: (f64, f64) = (174.62852, -36.886249);
 
-fn extract_address(o: &serde_json::Value) -> String {
-    let destination = &o["destination"];
-    let address = &destination["address"];
-    let street = address["street"].as_str().unwrap_or("");
-    let city = address["city"].as_str().unwrap_or("");
-    let county = address["county"].as_str().unwrap_or("");
-    let postcode = address["postcode"].as_str().unwrap_or("");
-
-    let out = format!("{},{},{},{}", street, city, county, postcode);
-
-    out
+fn extract_address(o: &Opportunity) -> String {
+    o.address()
+        .map(|address| address.to_string())
+        .unwrap_or_else(|| String::from(",,,"))
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -40,6 +33,7 @@ enum JobType {
 
 #[derive(Clone, Debug)]
 struct Job {
+    #[allow(dead_code)] //< Only reported through Debug.
     address: String,
     location: (f64, f64), // Lat/Long of destination
     job_type: JobType,    // Enum for the job role.
@@ -51,8 +45,8 @@ struct SearchNode {
     value: Duration,
 }
 
-fn create_job(opportunity: &serde_json::Value, job_type: JobType) -> Option<Job> {
-    let address = extract_address(&opportunity);
+fn create_job(opportunity: &Opportunity, job_type: JobType) -> Option<Job> {
+    let address = extract_address(opportunity);
     let location = geolocate::locate(&address)?;
 
     Some(Job {
@@ -63,7 +57,6 @@ fn create_job(opportunity: &serde_json::Value, job_type: JobType) -> Option<Job>
     })
 }
 
-
 // Inject the required warehouse stops for this route?
 //   1. We go to the warehouse between any collection and delivery.
 fn validate_route(route: &[usize], jobs: &[Job]) -> Vec<usize> {
@@ -107,45 +100,49 @@ fn calculate_route_distance(route: &[usize], edges: &[SearchNode], jobs: &[Job])
         let i = route[route.len() - 1];
         if jobs[route[i - 1]].job_type == JobType::Collection {
             // Add unpacking time. (30min..?)
-            r = r + jobs[i].reserve;
+            r += jobs[i].reserve;
         }
     }
     r
 }
 
 fn main() -> anyhow::Result<()> {
-    dotenv::dotenv().expect("Failed to read .env file");
+    dotenv::dotenv().ok();
 
-    let today = Local::today();
+    let today = Local::now().date_naive();
     let date = today + Duration::days(3);
-    let auth_cache = schedule_assistant::authentication::AuthenticationCache::new();
+    let auth_cache = schedule_assistant::authentication::AuthenticationCache::new()?;
 
-    // Pull all currentrms::opportunities.
-    let opportunities = current_rms::opportunities(&auth_cache)?;
+    // Pull the confirmed currentrms::opportunities around the given day, allowing a
+    // day either side so that the difference between local time and UTC doesn't
+    // exclude anything.
+    let query = current_rms::Query::new()
+        .state(STATE_ORDER)
+        .starts_before(&(date + Duration::days(2)))
+        .ends_from(&(date - Duration::days(1)));
+    let opportunities = current_rms::opportunities_matching(&auth_cache, &query)?;
 
     // Create jobs for the given day.
     let mut jobs = Vec::new();
     for o in &opportunities {
         // State == 3 is an order.
-        if o["state"].as_i64().unwrap_or(0) != 3 {
+        if !current_rms::opportunity_is_confirmed(o) {
             continue;
         }
 
         // Create a job for the delivery if it set for the given day.
-        let starts_at_utc = DateTime::parse_from_rfc3339(o["starts_at"].as_str().unwrap()).unwrap();
-        let starts_at_local: DateTime<Local> = DateTime::from(starts_at_utc);
-        if starts_at_local.date() == date {
-            match create_job(&o, JobType::Delivery) {
+        let starts_at_local: DateTime<Local> = DateTime::from(o.starts_at);
+        if starts_at_local.date_naive() == date {
+            match create_job(o, JobType::Delivery) {
                 Some(job) => jobs.push(job),
                 None => println!("Unable to find location for job"),
             }
         }
 
         // Create a job for the collection if it set for on the given day.
-        let ends_at_utc = DateTime::parse_from_rfc3339(o["ends_at"].as_str().unwrap()).unwrap();
-        let ends_at_local: DateTime<Local> = DateTime::from(ends_at_utc);
-        if ends_at_local.date() == date {
-            match create_job(&o, JobType::Collection) {
+        let ends_at_local: DateTime<Local> = DateTime::from(o.ends_at);
+        if ends_at_local.date_naive() == date {
+            match create_job(o, JobType::Collection) {
                 Some(job) => jobs.push(job),
                 None => println!("Unable to find location for job"),
             }
@@ -168,7 +165,7 @@ fn main() -> anyhow::Result<()> {
 
     // Create an enumeration of pairs in this vector, we need one for every possible combination.
     let mut combinations = Vec::new();
-    let count = jobs.iter().count();
+    let count = jobs.len();
     for i in 0..count {
         for j in i + 1..count {
             let edge = (i, j);
@@ -178,7 +175,7 @@ fn main() -> anyhow::Result<()> {
             let value = match geolocate::directions(&coords) {
                 Ok(json) => {
                     // Parse the returned json string to extrat the distance and time values.
-                    assert!( json["code"].as_str().unwrap() == "Okay" );
+                    assert!(json["code"].as_str() == Some("Ok"));
                     let routes = &json["routes"];
 
                     //@note:  Do we care about any other routes other than the first?
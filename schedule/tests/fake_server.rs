@@ -0,0 +1,50 @@
+use chrono::{Duration, Local, TimeZone, Utc};
+use fake_server::{FakeServer, Fixtures};
+use serde_json::json;
+
+#[test]
+fn plans_a_route_for_the_deliveries_in_three_days() {
+    // The schedule is always planned for three days out.
+    let date = Local::now().date_naive() + Duration::days(3);
+    let at = |days: i64, hour: u32| {
+        let local = (date + Duration::days(days))
+            .and_hms_opt(hour, 0, 0)
+            .unwrap();
+        Local
+            .from_local_datetime(&local)
+            .unwrap()
+            .with_timezone(&Utc)
+            .format("%Y-%m-%dT%H:%M:%S%.3fZ")
+            .to_string()
+    };
+
+    let fixtures = Fixtures {
+        opportunities: vec![json!({
+            "id": 201,
+            "subject": "Garden party",
+            "starts_at": at(0, 10),
+            "ends_at": at(2, 10),
+            "state": 3,
+            "state_name": "Order",
+            "status": 0,
+            "status_name": "Open",
+            "destination": {
+                "address": {
+                    "street": "1 Queen Street",
+                    "city": "Auckland",
+                    "postcode": "1010"
+                }
+            }
+        })],
+        ..Fixtures::default()
+    };
+    let server = FakeServer::start(fixtures).unwrap();
+    let output = server
+        .command(env!("CARGO_BIN_EXE_schedule"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("There are 1 jobs"), "{}", stdout);
+}
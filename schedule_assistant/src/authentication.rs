@@ -1,17 +1,64 @@
+use crate::config::ServiceUrls;
+use crate::error::{Error, Result};
+use crate::oauth;
 use crate::retrieve::authentication::*;
+use crate::retrieve::cassette::{Cassette, Mode};
+use crate::retrieve::client::ApiClient;
 use std::env;
+use std::path::Path;
 
 pub struct AuthenticationCache {
-    pub currentrms: BearerAuthentication,
+    pub currentrms: CurrentRmsAuthentication,
     pub servicem8: BasicAuthentication,
+    // Shared by every request so that connections are reused between them.
+    client: ApiClient,
+    urls: ServiceUrls,
 }
 
 impl AuthenticationCache {
-    pub fn new() -> AuthenticationCache {
-        AuthenticationCache {
-            currentrms: current_rms().expect("Unable to create the current_rms endpoint"),
-            servicem8: servicem8(),
+    pub fn new() -> Result<AuthenticationCache> {
+        Self::with_urls(ServiceUrls::from_env()?)
+    }
+
+    // Talks to the services at the given urls rather than those configured in
+    // the environment.
+    pub fn with_urls(urls: ServiceUrls) -> Result<AuthenticationCache> {
+        let mut client = ApiClient::new()?;
+        match Cassette::from_env()? {
+            Some(cassette) if cassette.mode() == Mode::Replay => {
+                return Self::replaying(urls, cassette)
+            }
+            Some(cassette) => client = client.with_cassette(cassette),
+            None => (),
         }
+
+        Ok(AuthenticationCache {
+            currentrms: current_rms(&urls)?,
+            servicem8: servicem8()?,
+            client,
+            urls,
+        })
+    }
+
+    // Answers every request from a recorded cassette rather than the services,
+    // so no credentials are needed.
+    pub fn replay(urls: ServiceUrls, cassette: &Path) -> Result<AuthenticationCache> {
+        Self::replaying(urls, Cassette::replay(cassette)?)
+    }
+
+    fn replaying(urls: ServiceUrls, cassette: Cassette) -> Result<AuthenticationCache> {
+        Ok(AuthenticationCache {
+            currentrms: CurrentRmsAuthentication::ApiKey(SubdomainAuthentication {
+                subdomain: String::from("replay"),
+                password: String::from("replay"),
+            }),
+            servicem8: BasicAuthentication {
+                username: String::from("replay"),
+                password: String::from("replay"),
+            },
+            client: ApiClient::new()?.with_cassette(cassette),
+            urls,
+        })
     }
 
     pub fn servicem8(&self) -> &impl Authentication {
@@ -21,125 +68,66 @@ impl AuthenticationCache {
     pub fn currentrms(&self) -> &impl Authentication {
         &self.currentrms
     }
-}
-
-fn current_rms() -> reqwest::Result<BearerAuthentication> {
-    use oauth2::basic::BasicClient;
-    use oauth2::reqwest::http_client;
-    use oauth2::{
-        AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, TokenResponse,
-        TokenUrl,
-    };
-    use std::io::{BufRead, BufReader, Write};
-    use std::net::TcpListener;
-    use url::Url;
-
-    let client_id = ClientId::new(
-        env::var("CURRENT_CLIENT_ID").expect("Missing the CURRENT_CLIENT_ID environment variable."),
-    );
-    let client_secret = ClientSecret::new(
-        env::var("CURRENT_CLIENT_SECRET")
-            .expect("Missing the CURRENT_CLIENT_SECRET environment variable."),
-    );
-    let subdomain = env::var("CURRENT_DOMAIN_NAME")
-        .expect("Missing the CURRENT_DOMAIN_NAME environment variable.");
-
-    let auth_url = AuthUrl::new(format!(
-        "https://{}.current-rms.com/oauth2/authorize",
-        subdomain
-    ))
-    .expect("Invalid authorization endpoint URL");
-    let token_url = TokenUrl::new(format!(
-        "https://{}.current-rms.com/oauth2/token",
-        subdomain
-    ))
-    .expect("Invalid token endpoint URL");
-
-    // Set up the config for the Github OAuth2 process.
-    let client = BasicClient::new(client_id, Some(client_secret), auth_url, Some(token_url))
-        // This example will be running its own server at localhost:8080.
-        // See below for the server implementation.
-        .set_redirect_url(
-            RedirectUrl::new("http://localhost:8080".to_string()).expect("Invalid redirect URL"),
-        );
-
-    // Generate the authorization URL to which we'll redirect the user.
-    let (authorize_url, csrf_state) = client.authorize_url(CsrfToken::new_random).url();
-
-    println!(
-        "Open this URL in your browser:\n{}\n",
-        authorize_url.to_string()
-    );
 
-    // A very naive implementation of the redirect server.
-    let mut token = String::from("");
-    let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
-    for stream in listener.incoming() {
-        if let Ok(mut stream) = stream {
-            let code;
-            let state;
-            {
-                let mut reader = BufReader::new(&stream);
-
-                let mut request_line = String::new();
-                reader.read_line(&mut request_line).unwrap();
-
-                let redirect_url = request_line.split_whitespace().nth(1).unwrap();
-                let url = Url::parse(&("http://localhost".to_string() + redirect_url)).unwrap();
-
-                let code_pair = url
-                    .query_pairs()
-                    .find(|pair| {
-                        let &(ref key, _) = pair;
-                        key == "code"
-                    })
-                    .unwrap();
-
-                let (_, value) = code_pair;
-                code = AuthorizationCode::new(value.into_owned());
-
-                let state_pair = url
-                    .query_pairs()
-                    .find(|pair| {
-                        let &(ref key, _) = pair;
-                        key == "state"
-                    })
-                    .unwrap();
+    pub(crate) fn client(&self) -> &ApiClient {
+        &self.client
+    }
 
-                let (_, value) = state_pair;
-                state = CsrfToken::new(value.into_owned());
-            }
+    pub fn urls(&self) -> &ServiceUrls {
+        &self.urls
+    }
 
-            let message = "Go back to your terminal :)";
-            let response = format!(
-                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
-                message.len(),
-                message
-            );
-            stream.write_all(response.as_bytes()).unwrap();
+    // The recorded requests which haven't been replayed yet, empty unless a
+    // cassette is being replayed.
+    pub fn unplayed(&self) -> Vec<String> {
+        match self.client.cassette() {
+            Some(cassette) if cassette.mode() == Mode::Replay => cassette.unplayed(),
+            _ => Vec::new(),
+        }
+    }
+}
 
-            println!("Github returned the following code:\n{}\n", code.secret());
-            println!(
-                "Github returned the following state:\n{} (expected `{}`)\n",
-                state.secret(),
-                csrf_state.secret()
-            );
+// Reads a required environment variable.
+pub(crate) fn env_var(name: &str) -> Result<String> {
+    env::var(name)
+        .map_err(|_| Error::Configuration(format!("missing the {} environment variable", name)))
+}
 
-            // Exchange the code with a token.
-            let token_res = client.exchange_code(code).request(http_client).unwrap();
-            println!("Github returned the following token:\n{:?}\n", token_res);
-            token = token_res.access_token().secret().clone();
+// Current RMS is authenticated with an API key when one is configured, which
+// allows unattended runs, and otherwise with OAuth.  CURRENT_AUTH_METHOD can be
+// set to either "api_key" or "oauth" to choose explicitly.
+fn current_rms(urls: &ServiceUrls) -> Result<CurrentRmsAuthentication> {
+    let subdomain = env_var("CURRENT_DOMAIN_NAME")?;
+    let method = match env::var("CURRENT_AUTH_METHOD") {
+        Ok(method) => method.to_lowercase(),
+        Err(_) if env::var("CURRENT_API_KEY").is_ok() => String::from("api_key"),
+        Err(_) => String::from("oauth"),
+    };
 
-            // The server will terminate itself after collecting the first code.
-            break;
+    match method.as_str() {
+        "api_key" => {
+            let password = env_var("CURRENT_API_KEY")?;
+            Ok(CurrentRmsAuthentication::ApiKey(SubdomainAuthentication {
+                subdomain,
+                password,
+            }))
+        }
+        "oauth" => {
+            let token = oauth::access_token(&urls.current_rms_web_for(&subdomain))?;
+            Ok(CurrentRmsAuthentication::OAuth(BearerAuthentication {
+                subdomain,
+                token,
+            }))
         }
+        _ => Err(Error::Configuration(format!(
+            "unknown CURRENT_AUTH_METHOD '{}', expected 'api_key' or 'oauth'",
+            method
+        ))),
     }
-
-    return Ok(BearerAuthentication { subdomain, token });
 }
 
-fn servicem8() -> BasicAuthentication {
-    let username = env::var("SERVICEM8_USERNAME").expect("SERVICEM8_USERNAME not found");
-    let password = env::var("SERVICEM8_PASSWORD").expect("SERVICEM8_PASSWORD not found");
-    BasicAuthentication { username, password }
+fn servicem8() -> Result<BasicAuthentication> {
+    let username = env_var("SERVICEM8_USERNAME")?;
+    let password = env_var("SERVICEM8_PASSWORD")?;
+    Ok(BasicAuthentication { username, password })
 }
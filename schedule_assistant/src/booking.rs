@@ -0,0 +1,452 @@
+// Creates the ServiceM8 records for a confirmed opportunity which hasn't been
+// scheduled yet, rather than someone keying them in by hand: the client when
+// there isn't one by the member's name, a job at the destination, a contact
+// for the member and provisional delivery and collection activities.  When the
+// member already has a job for it only a missing leg is added, and anything
+// else is left for someone to look at rather than booking it twice.
+//
+// The activities are assigned to SERVICEM8_STAFF_UUID when it is set, and left
+// unassigned for the office to allocate otherwise.
+
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+use std::env;
+use std::fmt;
+
+use crate::authentication::AuthenticationCache;
+use crate::error::Result;
+use crate::models::format::servicem8_date;
+use crate::models::{Address, Company, Job, Member, Opportunity};
+use crate::servicem8::{self, Table};
+use crate::{matching, references, Explanation, Leg, Mismatch};
+
+// How long the provisional delivery and collection are booked for.
+const VISIT_HOURS: i64 = 2;
+
+// A job is created with this status, it is already a confirmed order.
+static JOB_STATUS: &str = "Work Order";
+
+#[derive(Clone, Debug)]
+pub enum Client {
+    Existing(Company),
+    New { name: String, address: Address },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Contact {
+    pub first: String,
+    pub last: String,
+    pub email: String,
+    pub phone: String,
+    pub mobile: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Visit {
+    pub leg: Leg,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+// Everything that will be created for one opportunity.
+#[derive(Clone, Debug)]
+pub struct Booking {
+    pub opportunity: Opportunity,
+    pub client: Client,
+    pub address: String,
+    pub contact: Option<Contact>,
+    pub visits: Vec<Visit>,
+}
+
+// What is done for an opportunity without a matching job.
+#[derive(Clone, Debug)]
+pub enum Action {
+    // The member has no job, so everything is created.
+    Create(Box<Booking>),
+    // The member's job is missing some of the legs, so only those are added.
+    AddVisits { job: String, visits: Vec<Visit> },
+}
+
+// Why an opportunity can't be booked automatically.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Unbookable {
+    NoMember,
+    NoAddress,
+    AlreadyScheduled { job: String },
+    LinkedJobMissing { job: String },
+    // The member has a job which may be for this booking but can't be fixed by
+    // adding a leg.
+    NeedsAttention { job: String, mismatch: Mismatch },
+    NoVisitTimes { job: String },
+}
+
+impl fmt::Display for Unbookable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unbookable::NoMember => write!(f, "the opportunity has no member to book it for"),
+            Unbookable::NoAddress => write!(f, "the opportunity has no destination address"),
+            Unbookable::AlreadyScheduled { job } => {
+                write!(f, "the opportunity is already scheduled as job {}", job)
+            }
+            Unbookable::LinkedJobMissing { job } => {
+                write!(
+                    f,
+                    "the opportunity is linked to job {} which doesn't exist",
+                    job
+                )
+            }
+            Unbookable::NeedsAttention { job, mismatch } => {
+                write!(f, "the member already has job {} but {}", job, mismatch)
+            }
+            Unbookable::NoVisitTimes { job } => write!(
+                f,
+                "there are no times for the legs missing from job {}",
+                job
+            ),
+        }
+    }
+}
+
+fn format_time(time: &DateTime<Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M").to_string()
+}
+
+impl fmt::Display for Visit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let leg = match self.leg {
+            Leg::Delivery => "delivery",
+            Leg::Collection => "collection",
+        };
+        write!(
+            f,
+            "a provisional {} from {} to {}",
+            leg,
+            format_time(&self.start),
+            format_time(&self.end)
+        )
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Create(booking) => write!(f, "{}", booking),
+            Action::AddVisits { job, visits } => {
+                for visit in visits {
+                    writeln!(f, "  add {} to job {}", visit, job)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Booking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.client {
+            Client::Existing(client) => writeln!(f, "  for the existing client {}", client.name)?,
+            Client::New { name, .. } => writeln!(f, "  create the client {}", name)?,
+        }
+        writeln!(f, "  create a job at {}", self.address)?;
+        if let Some(contact) = &self.contact {
+            let reach = [&contact.email, &contact.mobile, &contact.phone]
+                .iter()
+                .filter(|detail| !detail.is_empty())
+                .map(|detail| detail.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(
+                f,
+                "  add the contact {} {} ({})",
+                contact.first, contact.last, reach
+            )?;
+        }
+        for visit in &self.visits {
+            writeln!(f, "  add {}", visit)?;
+        }
+        Ok(())
+    }
+}
+
+// The member as a job contact, so long as there is some way of reaching them.
+fn contact_for(member: &Member) -> Option<Contact> {
+    let mut words: Vec<&str> = member.name.split_whitespace().collect();
+    let last = if words.len() > 1 {
+        words.pop().unwrap_or_default()
+    } else {
+        ""
+    };
+    let is_mobile = |name: &Option<String>| {
+        name.as_deref()
+            .is_some_and(|name| name.to_lowercase().contains("mobile"))
+    };
+    let number = |mobile: bool| {
+        member
+            .phones
+            .iter()
+            .find(|phone| is_mobile(&phone.phone_type_name) == mobile)
+            .map(|phone| phone.number.trim().to_string())
+            .unwrap_or_default()
+    };
+
+    let contact = Contact {
+        first: words.join(" "),
+        last: last.to_string(),
+        email: member.primary_email().unwrap_or_default().to_string(),
+        phone: number(false),
+        mobile: number(true),
+    };
+    if contact.email.is_empty() && contact.phone.is_empty() && contact.mobile.is_empty() {
+        None
+    } else {
+        Some(contact)
+    }
+}
+
+// The delivery finishes as the event starts and the collection starts as it
+// ends, unless they have been booked for particular times.  Neither is moved
+// onto another day to fit the whole visit in.
+fn visits_for(opportunity: &Opportunity) -> Vec<Visit> {
+    let length = Duration::hours(VISIT_HOURS);
+    let delivery = match opportunity.deliver_starts_at {
+        Some(start) => (start, start + length),
+        None => {
+            let end = opportunity.starts_at;
+            let midnight = end
+                .date_naive()
+                .and_hms_opt(0, 0, 0)
+                .map(|midnight| midnight.and_utc())
+                .unwrap_or(end);
+            ((end - length).max(midnight), end)
+        }
+    };
+    let collection = {
+        let start = opportunity.collect_starts_at.unwrap_or(opportunity.ends_at);
+        let end_of_day = start
+            .date_naive()
+            .and_hms_opt(23, 59, 59)
+            .map(|end| end.and_utc())
+            .unwrap_or(start);
+        (start, (start + length).min(end_of_day))
+    };
+    vec![
+        Visit {
+            leg: Leg::Delivery,
+            start: delivery.0,
+            end: delivery.1,
+        },
+        Visit {
+            leg: Leg::Collection,
+            start: collection.0,
+            end: collection.1,
+        },
+    ]
+}
+
+// Decides what to do from why the opportunity didn't match any job.  Jobs for
+// someone else, or for another opportunity, don't count, and neither does one
+// whose client has gone since there's no telling who it was for.  A job of the
+// member's with activities elsewhere is an earlier booking unless it is the
+// job to reconcile, see Dataset::job_to_reconcile.  The opportunity's own job,
+// linked or referring to it, has any legs it's missing added, even when a run
+// stopped before adding any.
+pub fn decide(
+    opportunity: &Opportunity,
+    explanation: &Explanation,
+    to_reconcile: Option<&Job>,
+    clients: &[Company],
+) -> std::result::Result<Action, Unbookable> {
+    let mismatches = match explanation {
+        Explanation::Matched { job, .. } => {
+            return Err(Unbookable::AlreadyScheduled { job: job.clone() })
+        }
+        Explanation::LinkedJobMissing { job } => {
+            return Err(Unbookable::LinkedJobMissing { job: job.clone() })
+        }
+        Explanation::NoMember => return Err(Unbookable::NoMember),
+        Explanation::NoJobs => {
+            return plan(opportunity, clients).map(|plan| Action::Create(Box::new(plan)))
+        }
+        Explanation::Unmatched(mismatches) => mismatches,
+    };
+
+    let members_jobs: Vec<&(String, Mismatch)> = mismatches
+        .iter()
+        .filter(|(job, mismatch)| match mismatch {
+            Mismatch::ClientName { .. }
+            | Mismatch::Contacts { .. }
+            | Mismatch::OtherOpportunity { .. }
+            | Mismatch::MissingClient { .. } => false,
+            Mismatch::ActivitiesOutsideWindow { .. } => {
+                to_reconcile.is_some_and(|to_reconcile| &to_reconcile.uuid == job)
+            }
+            _ => true,
+        })
+        .collect();
+
+    // Only a linked or referenced job can be the job to reconcile without any
+    // activities.
+    let is_own = |job: &String| to_reconcile.is_some_and(|own| &own.uuid == job);
+    let missing_legs = members_jobs.iter().find_map(|(job, mismatch)| {
+        let legs = match mismatch {
+            Mismatch::MissingDelivery { .. } => vec![Leg::Delivery],
+            Mismatch::MissingCollection { .. } => vec![Leg::Collection],
+            Mismatch::NoActivities if is_own(job) => vec![Leg::Delivery, Leg::Collection],
+            _ => return None,
+        };
+        Some((job, legs))
+    });
+    if let Some((job, legs)) = missing_legs {
+        let visits: Vec<Visit> = visits_for(opportunity)
+            .into_iter()
+            .filter(|visit| legs.contains(&visit.leg))
+            .collect();
+        if visits.len() < legs.len() {
+            return Err(Unbookable::NoVisitTimes { job: job.clone() });
+        }
+        return Ok(Action::AddVisits {
+            job: job.clone(),
+            visits,
+        });
+    }
+
+    match members_jobs.first() {
+        Some((job, mismatch)) => Err(Unbookable::NeedsAttention {
+            job: job.clone(),
+            mismatch: mismatch.clone(),
+        }),
+        None => plan(opportunity, clients).map(|plan| Action::Create(Box::new(plan))),
+    }
+}
+
+// Works out what needs creating for the opportunity.  A client is only reused
+// when it is the one active client with the member's name, a similar name may
+// well be someone else and the job would be booked against them.
+pub fn plan(
+    opportunity: &Opportunity,
+    clients: &[Company],
+) -> std::result::Result<Booking, Unbookable> {
+    let member = opportunity.member.as_ref().ok_or(Unbookable::NoMember)?;
+    let address = opportunity
+        .address()
+        .filter(|address| !address.one_line().is_empty())
+        .ok_or(Unbookable::NoAddress)?;
+
+    let name = matching::normalize_name(&member.name);
+    let same_name: Vec<&Company> = clients
+        .iter()
+        .filter(|client| client.active && matching::normalize_name(&client.name) == name)
+        .collect();
+    let client = match same_name.as_slice() {
+        [client] => Client::Existing((*client).clone()),
+        _ => Client::New {
+            name: member.name.clone(),
+            address: address.clone(),
+        },
+    };
+
+    Ok(Booking {
+        opportunity: opportunity.clone(),
+        client,
+        address: address.one_line(),
+        contact: contact_for(member),
+        visits: visits_for(opportunity),
+    })
+}
+
+fn company_fields(name: &str, address: &Address) -> Value {
+    json!({
+        "name": name,
+        "address": address.one_line(),
+        "address_street": address.street.clone().unwrap_or_default(),
+        "address_city": address.city.clone().unwrap_or_default(),
+        "address_postcode": address.postcode.clone().unwrap_or_default(),
+    })
+}
+
+// The client as it will be once created, so that later bookings in the same
+// run use it rather than creating it again.
+pub fn new_company(name: &str, address: &Address, uuid: &str) -> Result<Company> {
+    let mut fields = company_fields(name, address);
+    fields["uuid"] = json!(uuid);
+    Ok(serde_json::from_value(fields)?)
+}
+
+// Creates the records in ServiceM8 and stores the new job against the
+// opportunity, returning the job's uuid.  A new client is added to clients as
+// soon as it exists, even if the rest fails.
+pub fn create(
+    auth_cache: &AuthenticationCache,
+    booking: &Booking,
+    clients: &mut Vec<Company>,
+) -> Result<String> {
+    let company_uuid = match &booking.client {
+        Client::Existing(client) => client.uuid.clone(),
+        Client::New { name, address } => {
+            let fields = company_fields(name, address);
+            let uuid = servicem8::create_record(auth_cache, Table::Company, &fields)?;
+            clients.push(new_company(name, address, &uuid)?);
+            uuid
+        }
+    };
+
+    let mut fields = references::new_job_fields(&booking.opportunity)?;
+    let extra = json!({
+        "company_uuid": company_uuid,
+        "job_address": booking.address,
+        "status": JOB_STATUS,
+        "date": booking.opportunity.starts_at.format("%Y-%m-%d 00:00:00").to_string(),
+    });
+    if let (Some(fields), Value::Object(extra)) = (fields.as_object_mut(), extra) {
+        fields.extend(extra);
+    }
+    let job_uuid = servicem8::create_record(auth_cache, Table::Job, &fields)?;
+
+    if let Some(contact) = &booking.contact {
+        servicem8::create_record(
+            auth_cache,
+            Table::JobContact,
+            &json!({
+                "job_uuid": job_uuid,
+                "first": contact.first,
+                "last": contact.last,
+                "email": contact.email,
+                "phone": contact.phone,
+                "mobile": contact.mobile,
+                "type": "JOB",
+            }),
+        )?;
+    }
+
+    for visit in &booking.visits {
+        add_visit(auth_cache, &job_uuid, visit)?;
+    }
+
+    // The job already refers to the opportunity, so this only updates the
+    // opportunity.
+    fields["uuid"] = json!(job_uuid);
+    let job: Job = serde_json::from_value(fields)?;
+    references::write_back(auth_cache, &booking.opportunity, &job)?;
+
+    Ok(job_uuid)
+}
+
+// Adds a provisional activity to the job, returning the activity's uuid.
+pub fn add_visit(
+    auth_cache: &AuthenticationCache,
+    job_uuid: &str,
+    visit: &Visit,
+) -> Result<String> {
+    let staff_uuid = env::var("SERVICEM8_STAFF_UUID").unwrap_or_default();
+    servicem8::create_record(
+        auth_cache,
+        Table::JobActivity,
+        &json!({
+            "job_uuid": job_uuid,
+            "staff_uuid": staff_uuid,
+            "start_date": visit.start.format(servicem8_date::FORMAT).to_string(),
+            "end_date": visit.end.format(servicem8_date::FORMAT).to_string(),
+            "activity_was_scheduled": 1,
+        }),
+    )
+}
@@ -0,0 +1,57 @@
+// Where each of the services lives.  The production services are used unless
+// overridden by an environment variable, which allows the tools to be pointed
+// at a staging tenant, a recording proxy or a local stand-in.
+//
+// CURRENT_RMS_API_URL  the Current RMS API, ie https://api.current-rms.com/api/v1
+// CURRENT_RMS_WEB_URL  the Current RMS web app used for OAuth and printing, any
+//                      {subdomain} within it is replaced with the account name
+// SERVICEM8_API_URL    the ServiceM8 API, ie https://api.servicem8.com/api_1.0
+
+use crate::error::{Error, Result};
+use std::env;
+use url::Url;
+
+static DEFAULT_CURRENT_RMS_API_URL: &str = "https://api.current-rms.com/api/v1";
+static DEFAULT_CURRENT_RMS_WEB_URL: &str = "https://{subdomain}.current-rms.com";
+static DEFAULT_SERVICEM8_API_URL: &str = "https://api.servicem8.com/api_1.0";
+
+#[derive(Clone, Debug)]
+pub struct ServiceUrls {
+    pub current_rms_api: String,
+    pub current_rms_web: String,
+    pub servicem8_api: String,
+}
+
+impl Default for ServiceUrls {
+    fn default() -> Self {
+        ServiceUrls {
+            current_rms_api: DEFAULT_CURRENT_RMS_API_URL.to_string(),
+            current_rms_web: DEFAULT_CURRENT_RMS_WEB_URL.to_string(),
+            servicem8_api: DEFAULT_SERVICEM8_API_URL.to_string(),
+        }
+    }
+}
+
+impl ServiceUrls {
+    pub fn from_env() -> Result<ServiceUrls> {
+        Ok(ServiceUrls {
+            current_rms_api: url_var("CURRENT_RMS_API_URL", DEFAULT_CURRENT_RMS_API_URL)?,
+            current_rms_web: url_var("CURRENT_RMS_WEB_URL", DEFAULT_CURRENT_RMS_WEB_URL)?,
+            servicem8_api: url_var("SERVICEM8_API_URL", DEFAULT_SERVICEM8_API_URL)?,
+        })
+    }
+
+    // The web app for a particular account.
+    pub fn current_rms_web_for(&self, subdomain: &str) -> String {
+        self.current_rms_web.replace("{subdomain}", subdomain)
+    }
+}
+
+// Reads a base URL, without any trailing slash so that paths can be appended.
+fn url_var(name: &str, default: &str) -> Result<String> {
+    let value = env::var(name).unwrap_or_else(|_| default.to_string());
+    let value = value.trim_end_matches('/').to_string();
+    Url::parse(&value.replace("{subdomain}", "subdomain"))
+        .map_err(|e| Error::Configuration(format!("invalid {} '{}': {}", name, value, e)))?;
+    Ok(value)
+}
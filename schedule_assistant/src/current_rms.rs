@@ -1,68 +1,284 @@
-use super::authentication::AuthenticationCache;
+use super::authentication::{self, AuthenticationCache};
 use super::endpoints::current_rms;
-use super::retrieve::fetch;
+use super::error::Result;
+use super::models::Opportunity;
+use super::retrieve::authentication::Authentication;
+use super::retrieve::client::ApiClient;
+use super::retrieve::endpoint::{PagedEndpoint, DEFAULT_PER_PAGE};
+use super::retrieve::{fetch, fetch_async};
 
-use serde_json::Value;
-use std::env;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
 
+// Most pages that are requested at the same time.
+const MAX_CONCURRENT_PAGES: usize = 4;
 
-// [Retrieval]
-// Retrieves opportunities which is active between the start and end dates.
-pub fn opportunities(auth_cache: &AuthenticationCache) -> reqwest::Result<Vec<Value>> {
-    let mut endpoint = current_rms::opportunities();
-    let authentication = auth_cache.currentrms();
-    let mut list = Vec::new();
-
-    loop {
-        let response = fetch::get(&endpoint, authentication)?;
-        // If there isnt an object then somethings gone wrong?
-        let object = &response["opportunities"];
-        if !object.is_array() {
-            //@todo: Should this be an error?
-            break;
+#[derive(Deserialize)]
+struct PageMeta {
+    total_row_count: u32,
+}
+
+// A page of any list resource, the records are held under the plural name of
+// the resource, ie "opportunities" or "members".
+struct Page<R> {
+    records: Vec<R>,
+    meta: Option<PageMeta>,
+}
+
+impl<R: DeserializeOwned> Page<R> {
+    fn from_value(mut value: Value, key: &str) -> Result<Page<R>> {
+        let records = match value.get_mut(key).map(Value::take) {
+            Some(Value::Null) | None => Vec::new(),
+            Some(records) => serde_json::from_value(records)?,
+        };
+        let meta = match value.get_mut("meta").map(Value::take) {
+            Some(meta) => serde_json::from_value(meta).ok(),
+            None => None,
+        };
+        Ok(Page { records, meta })
+    }
+
+    // Whether there are pages after this one.
+    fn has_more(&self, endpoint: &PagedEndpoint) -> bool {
+        match &self.meta {
+            Some(meta) => endpoint.page < meta.total_row_count.div_ceil(endpoint.per_page),
+            // Without the count a short page is the last one.
+            None => self.records.len() as u32 >= endpoint.per_page,
         }
+    }
+}
 
-        // If there are no more objects then we're done.
-        let objects = object.as_array().unwrap();
-        if objects.is_empty() {
-            break;
+pub use super::retrieve::query::{Query, QueryDate};
+
+// Lazily walks through the pages of a list resource, only requesting the next
+// page once the records of the previous one have been used up.
+pub struct Pages<'a, R> {
+    auth_cache: &'a AuthenticationCache,
+    endpoint: PagedEndpoint,
+    key: &'static str,
+    records: VecDeque<R>,
+    finished: bool,
+}
+
+impl<'a, R: DeserializeOwned> Pages<'a, R> {
+    fn new(
+        auth_cache: &'a AuthenticationCache,
+        endpoint: PagedEndpoint,
+        key: &'static str,
+    ) -> Self {
+        Pages {
+            auth_cache,
+            endpoint,
+            key,
+            records: VecDeque::new(),
+            finished: false,
         }
+    }
 
-        // Cloning all the objects found and push all these structures into a vector to
-        // be returned.
-        list.extend(objects.clone().into_iter());
-        endpoint.advance();
+    // Number of records to request with each page, this can only be changed
+    // before iterating.
+    pub fn per_page(mut self, per_page: u32) -> Self {
+        self.endpoint.per_page = per_page.clamp(1, DEFAULT_PER_PAGE);
+        self
     }
 
+    fn fetch_page(&mut self) -> Result<()> {
+        let value: Value = fetch::get(
+            self.auth_cache.client(),
+            &self.endpoint,
+            self.auth_cache.currentrms(),
+        )?;
+        let page = Page::<R>::from_value(value, self.key)?;
+        self.finished = page.records.is_empty() || !page.has_more(&self.endpoint);
+        self.records.extend(page.records);
+        self.endpoint.advance();
+        Ok(())
+    }
+}
+
+impl<R: DeserializeOwned> Iterator for Pages<'_, R> {
+    type Item = Result<R>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.records.is_empty() && !self.finished {
+            if let Err(e) = self.fetch_page() {
+                self.finished = true;
+                return Some(Err(e));
+            }
+        }
+        self.records.pop_front().map(Ok)
+    }
+}
+
+// [Retrieval]
+// Retrieves every opportunity in the account.
+pub fn opportunities(auth_cache: &AuthenticationCache) -> Result<Vec<Opportunity>> {
+    opportunities_matching(auth_cache, &Query::new())
+}
+
+// Retrieves only the opportunities which match the query, the filtering is done
+// by Current RMS so only the matching pages are downloaded.
+pub fn opportunities_matching(
+    auth_cache: &AuthenticationCache,
+    query: &Query,
+) -> Result<Vec<Opportunity>> {
+    let endpoint = current_rms::opportunities(&auth_cache.urls().current_rms_api, query);
+    let client = auth_cache.client();
+    let list = client.block_on(fetch_pages(
+        client,
+        &endpoint,
+        auth_cache.currentrms(),
+        "opportunities",
+    ))?;
     println!("Found {} opportunities", list.len());
     Ok(list)
 }
 
-pub fn mark_as_lost(auth_cache: &AuthenticationCache, opportunity_id: u64) -> reqwest::Result<Value> {
-    let endpoint = current_rms::mark_as_dead(opportunity_id);
+// As opportunities_matching but the opportunities are downloaded a page at a
+// time as they are iterated over, so the caller can stop early.
+pub fn opportunity_pages<'a>(
+    auth_cache: &'a AuthenticationCache,
+    query: &Query,
+) -> Pages<'a, Opportunity> {
+    Pages::new(
+        auth_cache,
+        current_rms::opportunities(&auth_cache.urls().current_rms_api, query),
+        "opportunities",
+    )
+}
+
+// The first page reports how many records there are, which lets the rest of the
+// pages be requested together.
+async fn fetch_pages<R: DeserializeOwned, U: Authentication>(
+    client: &ApiClient,
+    endpoint: &PagedEndpoint,
+    authentication: &U,
+    key: &str,
+) -> Result<Vec<R>> {
+    let value: Value = fetch_async::get(client, endpoint, authentication).await?;
+    let first = Page::<R>::from_value(value, key)?;
+    let more = first.has_more(endpoint);
+    let mut list = first.records;
+    if !more {
+        return Ok(list);
+    }
+
+    match first.meta {
+        Some(meta) => {
+            let pages = meta.total_row_count.div_ceil(endpoint.per_page);
+            let rest: Vec<Value> = stream::iter(endpoint.page + 1..=pages)
+                .map(|page| {
+                    let endpoint = endpoint.at_page(page);
+                    async move { fetch_async::get(client, &endpoint, authentication).await }
+                })
+                .buffered(MAX_CONCURRENT_PAGES)
+                .try_collect()
+                .await?;
+            for value in rest {
+                list.extend(Page::<R>::from_value(value, key)?.records);
+            }
+        }
+        // Without the count keep going until we get a short page.
+        None => {
+            let mut endpoint = endpoint.clone();
+            loop {
+                endpoint.advance();
+                let value: Value = fetch_async::get(client, &endpoint, authentication).await?;
+                let page = Page::<R>::from_value(value, key)?;
+                let more = page.has_more(&endpoint);
+                list.extend(page.records);
+                if !more {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(list)
+}
+
+#[derive(Deserialize)]
+struct OpportunityResponse {
+    opportunity: Opportunity,
+}
+
+// [Modification]
+// Current RMS expects the attributes of the opportunity to be wrapped in an
+// "opportunity" object, which is done here so callers only supply the fields.
+pub fn create_opportunity(auth_cache: &AuthenticationCache, fields: &Value) -> Result<Opportunity> {
+    let endpoint = current_rms::new_opportunity(&auth_cache.urls().current_rms_api);
+    let authentication = auth_cache.currentrms();
+    let body = json!({ "opportunity": fields });
+    let response: OpportunityResponse =
+        fetch::post(auth_cache.client(), &endpoint, authentication, &body)?;
+    Ok(response.opportunity)
+}
+
+pub fn update_opportunity(
+    auth_cache: &AuthenticationCache,
+    opportunity_id: u64,
+    fields: &Value,
+) -> Result<Opportunity> {
+    let endpoint = current_rms::opportunity(&auth_cache.urls().current_rms_api, opportunity_id);
+    let authentication = auth_cache.currentrms();
+    let body = json!({ "opportunity": fields });
+    let response: OpportunityResponse =
+        fetch::put(auth_cache.client(), &endpoint, authentication, &body)?;
+    Ok(response.opportunity)
+}
+
+pub fn delete_opportunity(auth_cache: &AuthenticationCache, opportunity_id: u64) -> Result<()> {
+    let endpoint = current_rms::opportunity(&auth_cache.urls().current_rms_api, opportunity_id);
+    let authentication = auth_cache.currentrms();
+    fetch::delete(auth_cache.client(), &endpoint, authentication)
+}
+
+// Performs one of the state changes that Current RMS exposes as actions on an
+// opportunity, ie "mark_as_dead" or "convert_to_order".
+pub fn opportunity_action(
+    auth_cache: &AuthenticationCache,
+    opportunity_id: u64,
+    action: &str,
+    fields: &Value,
+) -> Result<Value> {
+    let endpoint =
+        current_rms::opportunity_action(&auth_cache.urls().current_rms_api, opportunity_id, action);
+    let authentication = auth_cache.currentrms();
+    fetch::post(auth_cache.client(), &endpoint, authentication, fields)
+}
+
+pub fn mark_as_lost(auth_cache: &AuthenticationCache, opportunity_id: u64) -> Result<Value> {
+    let endpoint = current_rms::mark_as_dead(&auth_cache.urls().current_rms_api, opportunity_id);
     let authentication = auth_cache.currentrms();
-    fetch::get(&endpoint, authentication)
+    fetch::post(auth_cache.client(), &endpoint, authentication, &json!({}))
 }
 
-pub fn opportunity_is_confirmed(op: &Value) -> bool {
-    op["state"].as_u64().unwrap_or(0) == 3
+pub fn opportunity_is_confirmed(op: &Opportunity) -> bool {
+    op.is_confirmed()
 }
 
 pub fn print_document_pdf(
     auth_cache: &AuthenticationCache,
     opportunity_id: u64,
     document_id: u64,
-) -> reqwest::Result<(Vec<u8>, String)> {
+) -> Result<(Vec<u8>, String)> {
     use reqwest::header::*;
     let authentication = auth_cache.currentrms();
-    let subdomain = env::var("CURRENT_DOMAIN_NAME").expect("CURRENT_DOMAIN_NAME not found");
-    let endpoint = current_rms::opportunity_print_document_pdf(&subdomain, opportunity_id, document_id);
+    let subdomain = authentication::env_var("CURRENT_DOMAIN_NAME")?;
+    let endpoint = current_rms::opportunity_print_document_pdf(
+        &auth_cache.urls().current_rms_web_for(&subdomain),
+        opportunity_id,
+        document_id,
+    );
 
     // Retrieve filename;
     let mut filename = String::from("picking_list.pdf");
-    let mut response = fetch::fetch(&endpoint, authentication)?;
-    if let Some(content_disposition) = response.headers().get(CONTENT_DISPOSITION) {
-        let mut str = content_disposition.to_str().unwrap();
+    let response = fetch::fetch(auth_cache.client(), &endpoint, authentication)?;
+    if let Some(content_disposition) = response.headers.get(CONTENT_DISPOSITION) {
+        let mut str = content_disposition.to_str().unwrap_or_default();
         if let Some(c) = str.find('"') {
             str = &str[(c + 1)..];
             if let Some(c) = str.find('"') {
@@ -72,7 +288,5 @@ pub fn print_document_pdf(
         }
     }
 
-    let mut buffer = Vec::new();
-    response.copy_to(&mut buffer)?;
-    Ok((buffer, filename))
+    Ok((response.body, filename))
 }
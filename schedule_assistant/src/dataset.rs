@@ -0,0 +1,270 @@
+// The ServiceM8 tables indexed by job and client, so that matching a week of
+// opportunities against years of jobs looks records up rather than scanning
+// every table for every job.
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::HashMap;
+
+use crate::error::Result;
+use crate::links::LinkStore;
+use crate::models::{Company, Job, JobActivity, JobContact, Opportunity};
+use crate::servicem8::Tables;
+use crate::{
+    job_is_for_member, job_mismatch, legs_mismatch, references, Explanation, MatchSource, Mismatch,
+};
+
+// Furthest that a job's activities can have drifted from the booking and still
+// be reconciled with it.
+const DRIFT_LIMIT_DAYS: i64 = 30;
+
+// How far the period is from the opportunity, zero when they overlap.
+fn distance_from(
+    opportunity: &Opportunity,
+    (start, end): (DateTime<Utc>, DateTime<Utc>),
+) -> Duration {
+    if end < opportunity.starts_at {
+        opportunity.starts_at - end
+    } else if start > opportunity.ends_at {
+        start - opportunity.ends_at
+    } else {
+        Duration::zero()
+    }
+}
+
+pub struct Dataset<'a> {
+    jobs: &'a [Job],
+    jobs_by_uuid: HashMap<&'a str, &'a Job>,
+    clients: HashMap<&'a str, &'a Company>,
+    contacts: HashMap<&'a str, Vec<&'a JobContact>>,
+    activities: HashMap<&'a str, Vec<&'a JobActivity>>,
+    // The opportunity that each job refers to, and the reverse.
+    references: HashMap<&'a str, u64>,
+    referenced_by: HashMap<u64, &'a Job>,
+}
+
+impl<'a> Dataset<'a> {
+    pub fn new(
+        jobs: &'a [Job],
+        clients: &'a [Company],
+        contacts: &'a [JobContact],
+        activities: &'a [JobActivity],
+    ) -> Self {
+        let mut dataset = Dataset {
+            jobs,
+            jobs_by_uuid: jobs.iter().map(|job| (job.uuid.as_str(), job)).collect(),
+            clients: clients
+                .iter()
+                .map(|client| (client.uuid.as_str(), client))
+                .collect(),
+            contacts: HashMap::new(),
+            activities: HashMap::new(),
+            references: HashMap::new(),
+            referenced_by: HashMap::new(),
+        };
+        for contact in contacts {
+            dataset
+                .contacts
+                .entry(contact.job_uuid.as_str())
+                .or_default()
+                .push(contact);
+        }
+        for activity in activities {
+            dataset
+                .activities
+                .entry(activity.job_uuid.as_str())
+                .or_default()
+                .push(activity);
+        }
+        for job in jobs {
+            if let Some(opportunity) = references::stored_opportunity(job) {
+                dataset.references.insert(job.uuid.as_str(), opportunity);
+                dataset.referenced_by.entry(opportunity).or_insert(job);
+            }
+        }
+        dataset
+    }
+
+    pub fn from_tables(tables: &'a Tables) -> Self {
+        Dataset::new(
+            &tables.jobs,
+            &tables.clients,
+            &tables.contacts,
+            &tables.activities,
+        )
+    }
+
+    pub fn jobs(&self) -> &'a [Job] {
+        self.jobs
+    }
+
+    pub fn job(&self, uuid: &str) -> Option<&'a Job> {
+        self.jobs_by_uuid.get(uuid).copied()
+    }
+
+    pub fn client(&self, job: &Job) -> Option<&'a Company> {
+        self.clients.get(job.company_uuid.as_str()).copied()
+    }
+
+    pub fn contacts(&self, job: &Job) -> &[&'a JobContact] {
+        self.contacts
+            .get(job.uuid.as_str())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn activities(&self, job: &Job) -> &[&'a JobActivity] {
+        self.activities
+            .get(job.uuid.as_str())
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn has_job(&self, links: &LinkStore, opportunity: &Opportunity) -> Result<bool> {
+        let explanation = self.explain(links, opportunity)?;
+        Ok(matches!(explanation, Explanation::Matched { .. }))
+    }
+
+    // Looks for the job belonging to the opportunity, and when there isn't one
+    // explains why each of the jobs was rejected.  A linked job, and then one
+    // that the records refer to, is used in place of matching, but still needs
+    // both legs to be scheduled.
+    pub fn explain(&self, links: &LinkStore, opportunity: &Opportunity) -> Result<Explanation> {
+        if let Some(uuid) = links.job_for(opportunity.id) {
+            return Ok(match self.job(uuid) {
+                Some(job) => self.explain_legs(opportunity, job, MatchSource::Linked),
+                None => Explanation::LinkedJobMissing {
+                    job: uuid.to_string(),
+                },
+            });
+        }
+
+        let stored = references::stored_job(opportunity)
+            .and_then(|uuid| self.job(uuid))
+            .or_else(|| self.referenced_by.get(&opportunity.id).copied());
+        if let Some(job) = stored {
+            return Ok(self.explain_legs(opportunity, job, MatchSource::Stored));
+        }
+
+        let member = match &opportunity.member {
+            Some(member) => member,
+            None => return Ok(Explanation::NoMember),
+        };
+        if self.jobs.is_empty() {
+            return Ok(Explanation::NoJobs);
+        }
+
+        let mut mismatches = Vec::new();
+        for job in self.jobs {
+            if let Some(&other) = self.references.get(job.uuid.as_str()) {
+                mismatches.push((
+                    job.uuid.clone(),
+                    Mismatch::OtherOpportunity { opportunity: other },
+                ));
+                continue;
+            }
+
+            // A job whose client has gone can't be checked, but the rest can.
+            let client = match self.client(job) {
+                Some(client) => client,
+                None => {
+                    mismatches.push((
+                        job.uuid.clone(),
+                        Mismatch::MissingClient {
+                            client: job.company_uuid.clone(),
+                        },
+                    ));
+                    continue;
+                }
+            };
+            let mismatch = job_mismatch(
+                opportunity,
+                member,
+                client,
+                self.activities(job),
+                self.contacts(job),
+            );
+            match mismatch {
+                Some(mismatch) => mismatches.push((job.uuid.clone(), mismatch)),
+                None => {
+                    return Ok(Explanation::Matched {
+                        job: job.uuid.clone(),
+                        source: MatchSource::Matched,
+                    })
+                }
+            }
+        }
+
+        Ok(Explanation::Unmatched(mismatches))
+    }
+
+    // The job is the opportunity's, so it only has to have both legs.
+    fn explain_legs(
+        &self,
+        opportunity: &Opportunity,
+        job: &Job,
+        source: MatchSource,
+    ) -> Explanation {
+        match legs_mismatch(opportunity, self.activities(job)) {
+            Some(mismatch) => Explanation::Unmatched(vec![(job.uuid.clone(), mismatch)]),
+            None => Explanation::Matched {
+                job: job.uuid.clone(),
+                source,
+            },
+        }
+    }
+
+    // The job whose activities should line up with the opportunity.  Unlike
+    // explain the legs aren't required to be near the booking, since a job
+    // which has drifted is the one that needs fixing: a linked or referenced
+    // job is used as is, otherwise the member's closest job which isn't for
+    // another opportunity.  Jobs with nothing within DRIFT_LIMIT_DAYS are more
+    // likely an earlier booking by the same client, so they are left alone.
+    pub fn job_to_reconcile(
+        &self,
+        links: &LinkStore,
+        opportunity: &Opportunity,
+    ) -> Option<&'a Job> {
+        if let Some(uuid) = links.job_for(opportunity.id) {
+            return self.job(uuid);
+        }
+        let stored = references::stored_job(opportunity)
+            .and_then(|uuid| self.job(uuid))
+            .or_else(|| self.referenced_by.get(&opportunity.id).copied());
+        if stored.is_some() {
+            return stored;
+        }
+
+        let member = opportunity.member.as_ref()?;
+        let limit = Duration::days(DRIFT_LIMIT_DAYS);
+        self.jobs
+            .iter()
+            .filter(|job| !self.references.contains_key(job.uuid.as_str()))
+            .filter(|job| job_is_for_member(member, self.client(job), self.contacts(job)))
+            .filter_map(|job| {
+                let distance = self
+                    .activities(job)
+                    .iter()
+                    .filter_map(|activity| activity.period())
+                    .map(|period| distance_from(opportunity, period))
+                    .min()?;
+                (distance <= limit).then_some((distance, job))
+            })
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, job)| job)
+    }
+
+    pub fn find_opportunity_for_job<'o>(
+        &self,
+        opportunities: &'o [Opportunity],
+        job: &Job,
+    ) -> Option<&'o Opportunity> {
+        let client = self.client(job)?;
+        crate::find_opportunity_for_job(
+            opportunities,
+            job,
+            client,
+            self.contacts(job),
+            self.activities(job),
+        )
+    }
+}
@@ -1,53 +1,141 @@
 //@todo Handle the "Sale" items during Finalise.
-//@todo Add the ability to do some basic filtering here, like passing through that we only
-//      want active opportunities or something.
 
 pub mod current_rms {
-    use crate::retrieve::endpoint::{BasicEndpoint, Endpoint, PagedEndpoint};
-    
-    static BASE_URL: &str = "https://api.current-rms.com/api/v1";
+    use crate::retrieve::endpoint::{
+        BasicEndpoint, Endpoint, PagedEndpoint, Service, DEFAULT_PER_PAGE,
+    };
+    use crate::retrieve::query::Query;
 
-    pub fn opportunities() -> PagedEndpoint {
-        let base_url = format!("{}/{}", BASE_URL, "opportunities");
-        PagedEndpoint { base_url, page: 0 }
+    pub fn opportunities(base: &str, query: &Query) -> PagedEndpoint {
+        let base_url = format!("{}/{}", base, "opportunities");
+        PagedEndpoint {
+            service: Service::CurrentRms,
+            base_url,
+            page: 1,
+            per_page: DEFAULT_PER_PAGE,
+            params: query.params().to_vec(),
+        }
     }
 
-    pub fn mark_as_dead(id: u64) -> impl Endpoint {
-        let base_url = format!("{}/opportunities/{}/mark_as_dead", BASE_URL, id);
-        BasicEndpoint { base_url }
+    // The collection itself, which is where new opportunities are posted.
+    pub fn new_opportunity(base: &str) -> BasicEndpoint {
+        let base_url = format!("{}/{}", base, "opportunities");
+        BasicEndpoint {
+            service: Service::CurrentRms,
+            base_url,
+            params: Vec::new(),
+        }
     }
 
-    pub fn opportunity_print_document_pdf(subdomain: &str, opportunity_id: u64, document_id: u64) -> impl Endpoint {
-        let base_url = format!("https://{}.current-rms.com/opportunities/{}/print_document.pdf?document_id={}", subdomain, opportunity_id, document_id);
-        BasicEndpoint { base_url }
+    pub fn opportunity(base: &str, id: u64) -> BasicEndpoint {
+        let base_url = format!("{}/opportunities/{}", base, id);
+        BasicEndpoint {
+            service: Service::CurrentRms,
+            base_url,
+            params: Vec::new(),
+        }
+    }
+
+    // Actions are posted to /opportunities/{id}/{action}, ie mark_as_dead.
+    pub fn opportunity_action(base: &str, id: u64, action: &str) -> BasicEndpoint {
+        let base_url = format!("{}/opportunities/{}/{}", base, id, action);
+        BasicEndpoint {
+            service: Service::CurrentRms,
+            base_url,
+            params: Vec::new(),
+        }
+    }
+
+    pub fn mark_as_dead(base: &str, id: u64) -> impl Endpoint {
+        opportunity_action(base, id, "mark_as_dead")
+    }
+
+    // The base is the web app for the account rather than the API.
+    pub fn opportunity_print_document_pdf(
+        web_base: &str,
+        opportunity_id: u64,
+        document_id: u64,
+    ) -> impl Endpoint {
+        let base_url = format!(
+            "{}/opportunities/{}/print_document.pdf?document_id={}",
+            web_base, opportunity_id, document_id
+        );
+        BasicEndpoint {
+            service: Service::CurrentRms,
+            base_url,
+            params: Vec::new(),
+        }
     }
 }
 
 pub mod servicem8 {
-    use crate::retrieve::endpoint::BasicEndpoint;
-    static BASE_URL: &str = "https://api.servicem8.com/api_1.0/";
+    use crate::retrieve::endpoint::{BasicEndpoint, Service};
+    use crate::retrieve::filter::Filter;
     static CLIENTS_URL: &str = "company.json";
     static JOB_ACTIVITIES_URL: &str = "jobactivity.json";
     static JOB_CONTACTS_URL: &str = "jobcontact.json";
     static JOBS_URL: &str = "job.json";
 
-    pub fn clients() -> BasicEndpoint {
-        let base_url = format!("{}{}", BASE_URL, CLIENTS_URL);
-        BasicEndpoint { base_url }
+    fn filter_params(filter: &Filter) -> Vec<(String, String)> {
+        match filter.expression() {
+            Some(expression) => vec![(String::from("$filter"), expression)],
+            None => Vec::new(),
+        }
+    }
+
+    pub fn clients(base: &str, filter: &Filter) -> BasicEndpoint {
+        let base_url = format!("{}/{}", base, CLIENTS_URL);
+        BasicEndpoint {
+            service: Service::ServiceM8,
+            base_url,
+            params: filter_params(filter),
+        }
+    }
+
+    pub fn activities(base: &str, filter: &Filter) -> BasicEndpoint {
+        let base_url = format!("{}/{}", base, JOB_ACTIVITIES_URL);
+        BasicEndpoint {
+            service: Service::ServiceM8,
+            base_url,
+            params: filter_params(filter),
+        }
+    }
+
+    pub fn contacts(base: &str, filter: &Filter) -> BasicEndpoint {
+        let base_url = format!("{}/{}", base, JOB_CONTACTS_URL);
+        BasicEndpoint {
+            service: Service::ServiceM8,
+            base_url,
+            params: filter_params(filter),
+        }
     }
 
-    pub fn activities() -> BasicEndpoint {
-        let base_url = format!("{}{}", BASE_URL, JOB_ACTIVITIES_URL);
-        BasicEndpoint { base_url }
+    pub fn jobs(base: &str, filter: &Filter) -> BasicEndpoint {
+        let base_url = format!("{}/{}", base, JOBS_URL);
+        BasicEndpoint {
+            service: Service::ServiceM8,
+            base_url,
+            params: filter_params(filter),
+        }
     }
 
-    pub fn contacts() -> BasicEndpoint {
-        let base_url = format!("{}{}", BASE_URL, JOB_CONTACTS_URL);
-        BasicEndpoint { base_url }
+    // Records are created by posting to the table, ie job.json.
+    pub fn table(base: &str, name: &str) -> BasicEndpoint {
+        let base_url = format!("{}/{}.json", base, name);
+        BasicEndpoint {
+            service: Service::ServiceM8,
+            base_url,
+            params: Vec::new(),
+        }
     }
 
-    pub fn jobs() -> BasicEndpoint {
-        let base_url = format!("{}{}", BASE_URL, JOBS_URL);
-        BasicEndpoint { base_url }
+    // An individual record, ie job/{uuid}.json, which is updated by posting to it.
+    pub fn record(base: &str, name: &str, uuid: &str) -> BasicEndpoint {
+        let base_url = format!("{}/{}/{}.json", base, name, uuid);
+        BasicEndpoint {
+            service: Service::ServiceM8,
+            base_url,
+            params: Vec::new(),
+        }
     }
 }
@@ -0,0 +1,89 @@
+// The error type returned by every public function in the crate.
+
+use std::{error, fmt, io};
+
+#[derive(Debug)]
+pub enum Error {
+    // The request couldn't be sent or the response couldn't be read.
+    Transport(reqwest::Error),
+    // The service answered but with a non-success status code.
+    Status {
+        url: String,
+        status: reqwest::StatusCode,
+        body: String,
+    },
+    // The response wasn't in the shape that we expected.
+    Json(serde_json::Error),
+    // A required setting, usually an environment variable, is missing or invalid.
+    Configuration(String),
+    // We were unable to authenticate against one of the services.
+    Authentication(String),
+    // The records retrieved don't agree with each other, ie a job referencing a
+    // client that doesn't exist.
+    DataIntegrity(String),
+    // A request which isn't in the cassette that is being replayed.
+    Replay(String),
+    // The local snapshot database couldn't be read or written.
+    Database(rusqlite::Error),
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "request failed: {}", e),
+            Error::Status { url, status, body } => {
+                write!(f, "{} returned {}", url, status)?;
+                if !body.is_empty() {
+                    write!(f, ": {}", body)?;
+                }
+                Ok(())
+            }
+            Error::Json(e) => write!(f, "unexpected response: {}", e),
+            Error::Configuration(message) => write!(f, "configuration error: {}", message),
+            Error::Authentication(message) => write!(f, "authentication failed: {}", message),
+            Error::DataIntegrity(message) => write!(f, "inconsistent data: {}", message),
+            Error::Replay(message) => write!(f, "nothing recorded for {}", message),
+            Error::Database(e) => write!(f, "snapshot database: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Transport(e) => Some(e),
+            Error::Json(e) => Some(e),
+            Error::Database(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        Error::Transport(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Database(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
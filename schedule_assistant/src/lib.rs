@@ -1,255 +1,476 @@
 pub mod authentication;
+pub mod booking;
+pub mod config;
+pub mod dataset;
 mod endpoints;
-pub mod json;
-mod macros;
+pub mod error;
+pub mod links;
+pub mod matching;
+pub mod models;
+mod oauth;
+pub mod reconcile;
+pub mod references;
 mod retrieve;
+pub mod snapshot;
+pub mod sync;
 
 pub mod current_rms;
 pub mod servicem8;
 
 use authentication::AuthenticationCache;
-use chrono::{Date, DateTime, Utc};
-use serde_json::Value;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use dataset::Dataset;
+pub use error::{Error, Result};
+use links::LinkStore;
+use models::{Company, Job, JobActivity, JobContact, Member, Opportunity};
+use reconcile::Reschedule;
+use std::fmt;
 
-fn date_bound_by(date: &Date<Utc>, start: &Date<Utc>, end: &Date<Utc>) -> bool {
+fn date_bound_by(date: &NaiveDate, start: &NaiveDate, end: &NaiveDate) -> bool {
     date > start && date < end
 }
 
-type Period<T> = (Date<T>, Date<T>);
-fn period_bound_by(period: &Period<Utc>, start: &Date<Utc>, end: &Date<Utc>) -> bool {
+type Period = (NaiveDate, NaiveDate);
+fn period_bound_by(period: &Period, start: &NaiveDate, end: &NaiveDate) -> bool {
     date_bound_by(&period.0, start, end) && date_bound_by(&period.1, start, end)
 }
 
-fn member_matches_client(member: &Value, client: &Value) -> bool {
-    let member_name = guard!(json::attribute_from_value(&member, "name"));
-    let mut attribute = guard!(json::attribute_from_value(&client, "name"));
-    let mut first_name = String::from("");
-    let mut last_name = String::from("");
-
-    if let Some(is_individual) = client["is_individual"].as_i64() {
-        if is_individual == 1 {
-            let c = attribute.find(',');
-            match c {
-                // client name is structured "lastname, firstname"
-                Some(c) => {
-                    last_name = attribute.drain(..c).collect();
-                    first_name = attribute[2..].to_string();
-                }
-                // client name either has no surname or is structured
-                // "firstname lastname"
-                None => {
-                    let c = attribute.find(' ').unwrap_or_else(|| attribute.len());
-                    first_name = attribute.drain(..c).collect();
-                    if !attribute.is_empty() {
-                        last_name = attribute;
-                    }
-                }
-            }
-        } else {
-            first_name = attribute;
-        }
-    }
-
-    let mut name = format!("{} {}", first_name, last_name).to_string();
-    name = name.trim().to_string();
-    member_name == name
+// Names are compared loosely so that "Smith, Jane" or a typo still match, see
+// the matching module for scoring every candidate rather than a yes or no.
+fn member_matches_client(member: &Member, client: &Company) -> bool {
+    matching::same_name(&member.name, &client.name)
 }
 
-fn member_matches_contact(member: &Value, contact: &Value) -> bool {
-    let first_name = guard!(json::attribute_from_value(&contact, "first"));
-    let last_name = guard!(json::attribute_from_value(&contact, "last"));
-    let full_name = format!("{} {}", first_name, last_name).trim().to_string();
-    let email = guard!(json::attribute_from_value(&contact, "email"));
-    let member_name = guard!(json::attribute_from_value(&member, "name"));
-    let member_email = guard!(json::attribute_from_value(&member, "email"));
-
-    member_name == full_name || member_email == email
+fn member_matches_contact(member: &Member, contact: &JobContact) -> bool {
+    let name_matches = matching::same_name(&member.name, &contact.full_name());
+    let email_matches = member
+        .email_addresses()
+        .any(|email| matching::same_email(email, &contact.email));
+    name_matches || email_matches
 }
 
 pub fn activity_within_date_range(
-    activity: &Value,
-    start: &Date<Utc>,
-    end: &Date<Utc>,
+    activity: &JobActivity,
+    start: &NaiveDate,
+    end: &NaiveDate,
 ) -> Option<bool> {
-    let starts_at = json::date_from_value(activity, "start_date", "%Y-%m-%d %H:%M:%S")?;
-    let ends_at = json::date_from_value(activity, "end_date", "%Y-%m-%d %H:%M:%S")?;
+    let (starts_at, ends_at) = activity.period()?;
     Some(period_bound_by(
-        &(starts_at.date(), ends_at.date()),
+        &(starts_at.date_naive(), ends_at.date_naive()),
         start,
         end,
     ))
 }
 
-pub fn opportunity_within_date_range(
-    op: &Value,
-    start: &Date<Utc>,
-    end: &Date<Utc>,
-) -> Option<bool> {
-    let starts_at = json::date_from_value(op, "starts_at", "%Y-%m-%dT%H:%M:%S%.3f%Z")?;
-    let ends_at = json::date_from_value(op, "ends_at", "%Y-%m-%dT%H:%M:%S%.3f%Z")?;
-    Some(period_bound_by(
-        &(starts_at.date(), ends_at.date()),
+pub fn opportunity_within_date_range(op: &Opportunity, start: &NaiveDate, end: &NaiveDate) -> bool {
+    period_bound_by(
+        &(op.starts_at.date_naive(), op.ends_at.date_naive()),
         start,
         end,
-    ))
+    )
 }
 
-// Job contacts should only be those associated with this job.
-fn opportunity_matches_job(
-    opportunity: &Value,
-    client: &Value,
-    job_activities: &Vec<&Value>,
-    job_contacts: &Vec<&Value>,
-) -> reqwest::Result<bool> {
-    let member = &opportunity["member"];
-    if !member.is_object() {
-        return Ok(false);
+// Days either side of the opportunity that its delivery and collection may be
+// scheduled on.
+const LEG_TOLERANCE_DAYS: i64 = 2;
+
+// The two visits that every hire needs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Leg {
+    Delivery,
+    Collection,
+}
+
+// Which leg of the opportunity the activity is, if it's near enough to be
+// either.  Activities starting in the first half of the hire are deliveries and
+// those in the second half collections.
+pub fn classify_activity(opportunity: &Opportunity, activity: &JobActivity) -> Option<Leg> {
+    let (start, _) = activity.period()?;
+    let tolerance = Duration::days(LEG_TOLERANCE_DAYS);
+    let earliest = opportunity.starts_at.date_naive() - tolerance;
+    let latest = opportunity.ends_at.date_naive() + tolerance;
+    if start.date_naive() < earliest || start.date_naive() > latest {
+        return None;
     }
 
-    let member_matches = member_matches_client(member, &client);
-    let contact_matches = job_contacts
-        .iter()
-        .any(|&contact| member_matches_contact(member, &contact));
-    if member_matches || contact_matches {
-        let starts_at =
-            json::date_from_value(opportunity, "starts_at", "%Y-%m-%dT%H:%M:%S%.3f%Z").unwrap();
-        let ends_at =
-            json::date_from_value(opportunity, "ends_at", "%Y-%m-%dT%H:%M:%S%.3f%Z").unwrap();
-        for activity in job_activities {
-            if let Some(value) =
-                activity_within_date_range(&activity, &starts_at.date(), &ends_at.date())
-            {
-                if value {
-                    return Ok(true);
-                }
+    let midpoint = opportunity.starts_at + (opportunity.ends_at - opportunity.starts_at) / 2;
+    if start < midpoint {
+        Some(Leg::Delivery)
+    } else {
+        Some(Leg::Collection)
+    }
+}
+
+// Why a job isn't the one for an opportunity.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mismatch {
+    // The client isn't the member and the job has no contacts to fall back on.
+    ClientName {
+        client: String,
+    },
+    // Neither the client nor any of the job's contacts is the member.
+    Contacts {
+        client: String,
+        contacts: Vec<String>,
+    },
+    NoActivities,
+    // None of the activities are near the dates of the opportunity.
+    ActivitiesOutsideWindow {
+        activities: usize,
+    },
+    // Only one of the legs has been scheduled.
+    MissingDelivery {
+        expected: NaiveDate,
+    },
+    MissingCollection {
+        expected: NaiveDate,
+    },
+    // The job refers to a different opportunity.
+    OtherOpportunity {
+        opportunity: u64,
+    },
+    // The job's client, by uuid, doesn't exist.
+    MissingClient {
+        client: String,
+    },
+}
+
+impl Mismatch {
+    // Whether the job was rejected on who it's for rather than when.
+    pub fn is_name_mismatch(&self) -> bool {
+        matches!(
+            self,
+            Mismatch::ClientName { .. } | Mismatch::Contacts { .. }
+        )
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::ClientName { client } => {
+                write!(
+                    f,
+                    "the client '{}' doesn't match and there are no contacts",
+                    client
+                )
+            }
+            Mismatch::Contacts { client, contacts } => write!(
+                f,
+                "neither the client '{}' nor the contacts ({}) match",
+                client,
+                contacts.join(", ")
+            ),
+            Mismatch::NoActivities => write!(f, "the job has no activities"),
+            Mismatch::ActivitiesOutsideWindow { activities } => write!(
+                f,
+                "none of the {} activities are near the dates of the opportunity",
+                activities
+            ),
+            Mismatch::MissingDelivery { expected } => {
+                write!(f, "no delivery is scheduled, expected on {}", expected)
+            }
+            Mismatch::MissingCollection { expected } => {
+                write!(f, "no collection is scheduled, expected on {}", expected)
+            }
+            Mismatch::OtherOpportunity { opportunity } => {
+                write!(f, "the job is for opportunity {}", opportunity)
+            }
+            Mismatch::MissingClient { client } => {
+                write!(f, "the job's client '{}' doesn't exist", client)
             }
         }
     }
+}
 
-    Ok(false)
+// How the job belonging to an opportunity was found.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchSource {
+    // From the link store.
+    Linked,
+    // From a reference written back to one of the records.
+    Stored,
+    // By comparing the records.
+    Matched,
 }
 
-pub fn opportunity_has_job(
-    opportunity: &Value,
-    jobs: &Vec<Value>,
-    clients: &Vec<Value>,
-    contacts: &Vec<Value>,
-    activities: &Vec<Value>,
+// The outcome of looking for the job belonging to an opportunity.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Explanation {
+    Matched { job: String, source: MatchSource },
+    // The opportunity is linked to a job that no longer exists.
+    LinkedJobMissing { job: String },
+    // The opportunity has no member to compare the jobs with.
+    NoMember,
+    NoJobs,
+    // Why each job, by uuid, didn't match.
+    Unmatched(Vec<(String, Mismatch)>),
+}
+
+// Whether the client, or any of the job's contacts, is the member.
+fn job_is_for_member(
+    member: &Member,
+    client: Option<&Company>,
+    job_contacts: &[&JobContact],
 ) -> bool {
-    jobs.iter()
-        .find(|&job| {
-            let job_uuid =
-                json::attribute_from_value(job, "uuid").expect("Unable to find uuid for job");
-            let company_uuid = json::attribute_from_value(job, "company_uuid")
-                .expect("Unable to find a company_uuid for this job.");
-            let client = clients
-                .iter()
-                .find(|&client| json::attribute_from_value(client, "uuid").unwrap() == company_uuid)
-                .expect("Unable to find the client related to this job");
-            let job_contacts = contacts
-                .iter()
-                .filter(|&contact| {
-                    json::attribute_from_value(contact, "job_uuid").unwrap() == job_uuid
-                })
-                .collect::<Vec<&Value>>();
-            let job_activities = activities
-                .iter()
-                .filter(|&activity| {
-                    json::attribute_from_value(activity, "job_uuid").unwrap() == job_uuid
-                })
-                .collect::<Vec<&Value>>();
-
-            match opportunity_matches_job(opportunity, client, &job_contacts, &job_activities) {
-                Ok(value) => value,
-                Err(err) => {
-                    println!("{}", err);
-                    false
-                }
+    client.is_some_and(|client| member_matches_client(member, client))
+        || job_contacts
+            .iter()
+            .any(|&contact| member_matches_contact(member, contact))
+}
+
+// Job contacts should only be those associated with this job.
+fn job_mismatch(
+    opportunity: &Opportunity,
+    member: &Member,
+    client: &Company,
+    job_activities: &[&JobActivity],
+    job_contacts: &[&JobContact],
+) -> Option<Mismatch> {
+    if !job_is_for_member(member, Some(client), job_contacts) {
+        let client = client.display_name();
+        return Some(if job_contacts.is_empty() {
+            Mismatch::ClientName { client }
+        } else {
+            Mismatch::Contacts {
+                client,
+                contacts: job_contacts.iter().map(|c| c.full_name()).collect(),
             }
-        })
-        .is_some()
+        });
+    }
+
+    legs_mismatch(opportunity, job_activities)
 }
 
+// Whether the job's activities include both legs of the opportunity.
+fn legs_mismatch(opportunity: &Opportunity, job_activities: &[&JobActivity]) -> Option<Mismatch> {
+    if job_activities.is_empty() {
+        return Some(Mismatch::NoActivities);
+    }
+    let legs: Vec<Leg> = job_activities
+        .iter()
+        .filter_map(|&activity| classify_activity(opportunity, activity))
+        .collect();
+    match (
+        legs.contains(&Leg::Delivery),
+        legs.contains(&Leg::Collection),
+    ) {
+        (true, true) => None,
+        (false, false) => Some(Mismatch::ActivitiesOutsideWindow {
+            activities: job_activities.len(),
+        }),
+        (false, true) => Some(Mismatch::MissingDelivery {
+            expected: opportunity.starts_at.date_naive(),
+        }),
+        (true, false) => Some(Mismatch::MissingCollection {
+            expected: opportunity.ends_at.date_naive(),
+        }),
+    }
+}
+
+fn opportunity_matches_job(
+    opportunity: &Opportunity,
+    client: &Company,
+    job_activities: &[&JobActivity],
+    job_contacts: &[&JobContact],
+) -> bool {
+    match &opportunity.member {
+        Some(member) => {
+            job_mismatch(opportunity, member, client, job_activities, job_contacts).is_none()
+        }
+        None => false,
+    }
+}
+
+pub fn opportunity_has_job(
+    links: &LinkStore,
+    opportunity: &Opportunity,
+    jobs: &[Job],
+    clients: &[Company],
+    contacts: &[JobContact],
+    activities: &[JobActivity],
+) -> Result<bool> {
+    Dataset::new(jobs, clients, contacts, activities).has_job(links, opportunity)
+}
+
+// See Dataset::explain, when checking more than one opportunity build the
+// Dataset once and use it directly.
+pub fn explain_opportunity(
+    links: &LinkStore,
+    opportunity: &Opportunity,
+    jobs: &[Job],
+    clients: &[Company],
+    contacts: &[JobContact],
+    activities: &[JobActivity],
+) -> Result<Explanation> {
+    Dataset::new(jobs, clients, contacts, activities).explain(links, opportunity)
+}
+
+// References stored in the records are preferred over matching, and
+// opportunities which refer to another job, or a job which refers to another
+// opportunity, are never matched.
 pub fn find_opportunity_for_job<'a>(
-    opportunities: &'a Vec<Value>,
-    client: &Value,
-    job_contacts: &Vec<&Value>,
-    job_activities: &Vec<&Value>,
-) -> Option<&'a Value> {
+    opportunities: &'a [Opportunity],
+    job: &Job,
+    client: &Company,
+    job_contacts: &[&JobContact],
+    job_activities: &[&JobActivity],
+) -> Option<&'a Opportunity> {
+    let stored = opportunities.iter().find(|&opportunity| {
+        references::stored_opportunity(job) == Some(opportunity.id)
+            || references::stored_job(opportunity) == Some(job.uuid.as_str())
+    });
+    if stored.is_some() || references::stored_opportunity(job).is_some() {
+        return stored;
+    }
+
     opportunities.iter().find(|&opportunity| {
-        opportunity_matches_job(opportunity, client, job_contacts, job_activities).unwrap_or(false)
+        references::stored_job(opportunity).is_none()
+            && opportunity_matches_job(opportunity, client, job_activities, job_contacts)
     })
 }
 
-pub fn check_bookings(
+// The confirmed orders within the window, Current RMS narrows these down and we
+// then apply the exact bounds.
+fn confirmed_orders(
     auth_cache: &AuthenticationCache,
-    start: Date<Utc>,
-    end: Date<Utc>,
-) -> reqwest::Result<Vec<Value>> {
-    // Get Current-RMS opportunities.
-    let opportunities = current_rms::opportunities(auth_cache)?
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<Vec<Opportunity>> {
+    let query = current_rms::Query::new()
+        .state(models::STATE_ORDER)
+        .starts_from(&start)
+        .ends_before(&end);
+    Ok(current_rms::opportunities_matching(auth_cache, &query)?
         .into_iter()
         .filter(|opportunity| {
             current_rms::opportunity_is_confirmed(opportunity)
-                && opportunity_within_date_range(opportunity, &start, &end).unwrap()
+                && opportunity_within_date_range(opportunity, &start, &end)
         })
-        .collect::<Vec<Value>>();
+        .collect::<Vec<Opportunity>>())
+}
+
+pub fn check_bookings(
+    auth_cache: &AuthenticationCache,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<Vec<Opportunity>> {
+    // Get Current-RMS opportunities.
+    let opportunities = confirmed_orders(auth_cache, start, end)?;
 
-    // Get ServiceM8 data that we're going to need.
-    let jobs = servicem8::jobs(auth_cache)?;
-    let clients = servicem8::clients(auth_cache)?;
-    let job_activities = servicem8::job_activities(auth_cache)?;
-    let job_contacts = servicem8::job_contacts(auth_cache)?;
+    // Get ServiceM8 data that we're going to need, the deliveries and collections
+    // can fall a little outside of the window.
+    let tolerance = Duration::days(LEG_TOLERANCE_DAYS);
+    let tables = servicem8::tables_matching(
+        auth_cache,
+        &servicem8::TableFilters {
+            activities: Some(servicem8::activities_between(
+                start - tolerance,
+                end + tolerance,
+            )),
+            ..servicem8::TableFilters::all()
+        },
+    )?;
 
     // Check that all the opportunities have jobs registered in servicem8 with allocated
     // Activities for delivery & collection.
-    let unscheduled = opportunities
-        .into_iter()
-        .filter(|opportunity| {
-            !opportunity_has_job(
-                &opportunity,
-                &jobs,
-                &clients,
-                &job_contacts,
-                &job_activities,
-            )
-        })
-        .collect::<Vec<Value>>();
+    let links = LinkStore::from_env()?;
+    let dataset = Dataset::from_tables(&tables);
+    let mut unscheduled = Vec::new();
+    for opportunity in opportunities {
+        if !dataset.has_job(&links, &opportunity)? {
+            unscheduled.push(opportunity);
+        }
+    }
 
     Ok(unscheduled)
 }
 
-pub fn remove_expired_quotes(auth_cache: &AuthenticationCache, date: DateTime<Utc>) {
-    // get all the opportunities
-    let opportunities = current_rms::opportunities(auth_cache).unwrap();
+// The confirmed orders within the window which have no matching job, with why
+// each of the jobs didn't match, along with the tables they were checked
+// against.  Every job and activity is checked, rather than those around the
+// window, so that a job whose activities are elsewhere is still found.
+pub fn unscheduled_bookings(
+    auth_cache: &AuthenticationCache,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<(Vec<(Opportunity, Explanation)>, servicem8::Tables)> {
+    let opportunities = confirmed_orders(auth_cache, start, end)?;
+    let tables = servicem8::tables(auth_cache)?;
+    let links = LinkStore::from_env()?;
+    let dataset = Dataset::from_tables(&tables);
+
+    let mut unscheduled = Vec::new();
+    for opportunity in opportunities {
+        let explanation = dataset.explain(&links, &opportunity)?;
+        if !matches!(explanation, Explanation::Matched { .. }) {
+            unscheduled.push((opportunity, explanation));
+        }
+    }
+    Ok((unscheduled, tables))
+}
+
+// Lists the activities which need to be moved because they no longer line up
+// with the booking of the opportunity that their job belongs to.
+pub fn reconcile_bookings(
+    auth_cache: &AuthenticationCache,
+    start: NaiveDate,
+    end: NaiveDate,
+) -> Result<Vec<Reschedule>> {
+    let opportunities = confirmed_orders(auth_cache, start, end)?;
+
+    // Every activity, since those which have drifted can be anywhere.
+    let tables = servicem8::tables(auth_cache)?;
+    let links = LinkStore::from_env()?;
+    let dataset = Dataset::from_tables(&tables);
+
+    let mut reschedules = Vec::new();
+    for opportunity in &opportunities {
+        if let Some(job) = dataset.job_to_reconcile(&links, opportunity) {
+            reschedules.extend(reconcile::reconcile_job(
+                opportunity,
+                &job.uuid,
+                dataset.activities(job),
+            ));
+        }
+    }
 
-    // filter for the opportunities in the quotation state
-    opportunities
+    Ok(reschedules)
+}
+
+// Marks every quote which started before date as lost, carrying on past any
+// that fail.  The quotes which couldn't be marked are returned with the reason.
+pub fn remove_expired_quotes(
+    auth_cache: &AuthenticationCache,
+    date: DateTime<Utc>,
+) -> Result<Vec<(u64, Error)>> {
+    // get the quotes which start before date
+    let query = current_rms::Query::new()
+        .state(models::STATE_QUOTATION)
+        .starts_before(&date);
+    let opportunities = current_rms::opportunities_matching(auth_cache, &query)?;
+
+    // filter for the opportunities in the quotation state and kill all the quotes
+    // earlier than date
+    let expired = opportunities
         .into_iter()
-        .filter(|opportunity| {
-            json::attribute_from_value(&opportunity, "state_name").unwrap() == "Quotation"
-        })
-        .for_each(|opportunity| {
-            // kill all the quotes earlier than date
-            //  If date is before 'X'
-            match json::date_from_value(&opportunity, "starts_at", "%Y-%m-%dT%H:%M:%S%.3f%Z") {
-                Some(start_date) => {
-                    if start_date < date {
-                        // cancel the opportunity, mark as dead?
-                        //println!("{}", serde_json::to_string_pretty(&opportunity).unwrap());
-                        //let opportunity_id = json::attribute_from_value(&opportunity, "id").unwrap();
-                        let opportunity_id = opportunity["id"].as_u64().unwrap();
-                        println!(
-                            "Attempting to cancel unconfirmed quote number {}.",
-                            opportunity_id
-                        );
-                        match current_rms::mark_as_lost(auth_cache, opportunity_id) {
-                            Err(e) => println!("Err: Failed to mark the event as lost. {}", e),
-                            _ => println!("Marked event as lost."),
-                        }
-                    }
-                }
-                None => {}
+        .filter(|opportunity| opportunity.state_name == "Quotation")
+        .filter(|opportunity| opportunity.starts_at < date);
+
+    let mut failures = Vec::new();
+    for opportunity in expired {
+        println!(
+            "Attempting to cancel unconfirmed quote number {}.",
+            opportunity.id
+        );
+        match current_rms::mark_as_lost(auth_cache, opportunity.id) {
+            Ok(_) => println!("Marked event as lost."),
+            Err(e) => {
+                println!("Unable to mark quote {} as lost: {}", opportunity.id, e);
+                failures.push((opportunity.id, e));
             }
-        });
+        }
+    }
+
+    Ok(failures)
 }
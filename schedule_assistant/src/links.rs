@@ -0,0 +1,109 @@
+// Confirmed links between Current RMS opportunities and ServiceM8 jobs.
+//
+// When the matching can't work out which job belongs to an opportunity, ie
+// because the job was booked under a different name, the link can be recorded
+// here by hand.  Links are checked before any matching is attempted.
+//
+// The links are kept in a JSON file, .opportunity_links.json in the current
+// directory unless LINKS_FILE is set.
+
+use crate::error::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+static DEFAULT_LINKS_FILE: &str = ".opportunity_links.json";
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Link {
+    pub opportunity_id: u64,
+    pub job_uuid: String,
+    // Who confirmed the link and when.
+    pub linked_by: String,
+    pub linked_at: DateTime<Utc>,
+}
+
+// A store without a path is held in memory only.
+#[derive(Default)]
+pub struct LinkStore {
+    path: Option<PathBuf>,
+    links: Vec<Link>,
+}
+
+impl LinkStore {
+    // Opens the store at path, which is created on the first change.
+    pub fn open(path: &Path) -> Result<LinkStore> {
+        let links = match fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(LinkStore {
+            path: Some(path.to_path_buf()),
+            links,
+        })
+    }
+
+    pub fn from_env() -> Result<LinkStore> {
+        let path = env::var("LINKS_FILE").unwrap_or_else(|_| String::from(DEFAULT_LINKS_FILE));
+        LinkStore::open(Path::new(&path))
+    }
+
+    pub fn links(&self) -> &[Link] {
+        &self.links
+    }
+
+    pub fn get(&self, opportunity_id: u64) -> Option<&Link> {
+        self.links
+            .iter()
+            .find(|link| link.opportunity_id == opportunity_id)
+    }
+
+    pub fn job_for(&self, opportunity_id: u64) -> Option<&str> {
+        self.get(opportunity_id).map(|link| link.job_uuid.as_str())
+    }
+
+    // Links the opportunity to the job, replacing any existing link for the
+    // opportunity.
+    pub fn add(&mut self, opportunity_id: u64, job_uuid: &str, linked_by: &str) -> Result<&Link> {
+        self.links
+            .retain(|link| link.opportunity_id != opportunity_id);
+        self.links.push(Link {
+            opportunity_id,
+            job_uuid: job_uuid.to_string(),
+            linked_by: linked_by.to_string(),
+            linked_at: Utc::now(),
+        });
+        self.save()?;
+        Ok(&self.links[self.links.len() - 1])
+    }
+
+    // Removes the link for the opportunity, returning it if there was one.
+    pub fn remove(&mut self, opportunity_id: u64) -> Result<Option<Link>> {
+        let index = self
+            .links
+            .iter()
+            .position(|link| link.opportunity_id == opportunity_id);
+        let removed = index.map(|index| self.links.remove(index));
+        if removed.is_some() {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    // Written to a temporary file first so that an interrupted write can't
+    // lose the existing links.
+    fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec_pretty(&self.links)?)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+// Scores how likely it is that a ServiceM8 job is the one for a Current RMS
+// opportunity.
+//
+// The two services are filled in by hand so the same person is often written
+// differently, ie "Smith, Jane" and "Jane Smith-Jones", or with a typo.  Each
+// piece of evidence (name, email, phone, address and dates) is scored from 0
+// to 1 after normalising it, and the confidence is the weighted average of the
+// evidence that is available for both records.
+
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+
+use crate::dataset::Dataset;
+use crate::models::{Company, Job, JobActivity, JobContact, Opportunity};
+
+// Candidates scoring below this are ignored unless another threshold is set.
+pub const DEFAULT_THRESHOLD: f64 = 0.75;
+
+// Lowest name similarity that we treat as the same person.
+pub const NAME_THRESHOLD: f64 = 0.88;
+
+// An activity this far outside the opportunity scores nothing for dates.
+const DATE_TOLERANCE_HOURS: f64 = 7.0 * 24.0;
+
+const NAME_WEIGHT: f64 = 0.4;
+const EMAIL_WEIGHT: f64 = 0.25;
+const PHONE_WEIGHT: f64 = 0.1;
+const ADDRESS_WEIGHT: f64 = 0.05;
+const DATES_WEIGHT: f64 = 0.2;
+
+// The evidence for a job, those which are None couldn't be compared because one
+// of the records doesn't have them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scores {
+    pub name: f64,
+    pub email: Option<f64>,
+    pub phone: Option<f64>,
+    pub address: Option<f64>,
+    pub dates: f64,
+}
+
+impl Scores {
+    pub fn confidence(&self) -> f64 {
+        let mut total = NAME_WEIGHT * self.name + DATES_WEIGHT * self.dates;
+        let mut weights = NAME_WEIGHT + DATES_WEIGHT;
+        for (score, weight) in [
+            (self.email, EMAIL_WEIGHT),
+            (self.phone, PHONE_WEIGHT),
+            (self.address, ADDRESS_WEIGHT),
+        ] {
+            if let Some(score) = score {
+                total += weight * score;
+                weights += weight;
+            }
+        }
+        total / weights
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Candidate<'a> {
+    pub job: &'a Job,
+    pub client: Option<&'a Company>,
+    pub scores: Scores,
+    pub confidence: f64,
+}
+
+pub struct Matcher<'a> {
+    dataset: Dataset<'a>,
+    threshold: f64,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(
+        jobs: &'a [Job],
+        clients: &'a [Company],
+        contacts: &'a [JobContact],
+        activities: &'a [JobActivity],
+    ) -> Self {
+        Matcher {
+            dataset: Dataset::new(jobs, clients, contacts, activities),
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+
+    // Lowest confidence, from 0 to 1, of the candidates to return.
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold.clamp(0.0, 1.0);
+        self
+    }
+
+    // The jobs which could be for the opportunity, most likely first.
+    pub fn candidates(&self, opportunity: &Opportunity) -> Vec<Candidate<'a>> {
+        let mut candidates: Vec<Candidate<'a>> = self
+            .dataset
+            .jobs()
+            .iter()
+            .map(|job| self.score(opportunity, job))
+            .filter(|candidate| candidate.confidence >= self.threshold)
+            .collect();
+        candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        candidates
+    }
+
+    pub fn best(&self, opportunity: &Opportunity) -> Option<Candidate<'a>> {
+        self.candidates(opportunity).into_iter().next()
+    }
+
+    pub fn score(&self, opportunity: &Opportunity, job: &'a Job) -> Candidate<'a> {
+        let client = self.dataset.client(job);
+        let scores = score(
+            opportunity,
+            job,
+            client,
+            self.dataset.contacts(job),
+            self.dataset.activities(job),
+        );
+        Candidate {
+            job,
+            client,
+            confidence: scores.confidence(),
+            scores,
+        }
+    }
+}
+
+pub fn score(
+    opportunity: &Opportunity,
+    job: &Job,
+    client: Option<&Company>,
+    contacts: &[&JobContact],
+    activities: &[&JobActivity],
+) -> Scores {
+    let member = opportunity.member.as_ref();
+    let member_name = member.map(|m| m.name.as_str()).unwrap_or_default();
+
+    let names = client
+        .map(|c| c.name.clone())
+        .into_iter()
+        .chain(contacts.iter().map(|c| c.full_name()));
+    let name = names
+        .map(|name| name_score(member_name, &name))
+        .fold(0.0, f64::max);
+
+    let member_emails: Vec<String> = member
+        .into_iter()
+        .flat_map(|m| m.email_addresses())
+        .filter_map(normalize_email)
+        .collect();
+    let contact_emails: Vec<String> = contacts
+        .iter()
+        .filter_map(|c| normalize_email(&c.email))
+        .collect();
+    let email = both_present(&member_emails, &contact_emails).then(|| {
+        let matches = member_emails.iter().any(|e| contact_emails.contains(e));
+        if matches {
+            1.0
+        } else {
+            0.0
+        }
+    });
+
+    let member_phones: Vec<String> = member
+        .into_iter()
+        .flat_map(|m| m.phones.iter())
+        .filter_map(|p| normalize_phone(&p.number))
+        .collect();
+    let contact_phones: Vec<String> = contacts
+        .iter()
+        .flat_map(|c| [c.phone.as_str(), c.mobile.as_str()])
+        .filter_map(normalize_phone)
+        .collect();
+    let phone = both_present(&member_phones, &contact_phones).then(|| {
+        let matches = member_phones.iter().any(|p| contact_phones.contains(p));
+        if matches {
+            1.0
+        } else {
+            0.0
+        }
+    });
+
+    let address = opportunity.address().and_then(|address| {
+        let wanted = tokens(&address.to_string());
+        let mut found = tokens(&job.job_address);
+        if let Some(client) = client {
+            found.extend(tokens(&client.address));
+        }
+        if wanted.is_empty() || found.is_empty() {
+            return None;
+        }
+        Some(wanted.intersection(&found).count() as f64 / wanted.len() as f64)
+    });
+
+    let dates = activities
+        .iter()
+        .filter_map(|activity| activity.period())
+        .map(|period| date_score(opportunity, period))
+        .fold(0.0, f64::max);
+
+    Scores {
+        name,
+        email,
+        phone,
+        address,
+        dates,
+    }
+}
+
+fn both_present<T>(a: &[T], b: &[T]) -> bool {
+    !a.is_empty() && !b.is_empty()
+}
+
+// 1 when the activity overlaps the opportunity, falling to 0 as it moves away.
+fn date_score(opportunity: &Opportunity, (start, end): (DateTime<Utc>, DateTime<Utc>)) -> f64 {
+    let gap = if end < opportunity.starts_at {
+        opportunity.starts_at - end
+    } else if start > opportunity.ends_at {
+        start - opportunity.ends_at
+    } else {
+        return 1.0;
+    };
+    (1.0 - gap.num_hours() as f64 / DATE_TOLERANCE_HOURS).max(0.0)
+}
+
+// Lowercase words with punctuation removed, "Smith, Jane" is read as "jane smith".
+pub fn normalize_name(name: &str) -> Vec<String> {
+    let name = match name.split_once(',') {
+        Some((last, first)) if !first.trim().is_empty() => format!("{} {}", first, last),
+        _ => name.to_string(),
+    };
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| match word {
+            "ltd" => String::from("limited"),
+            word => word.to_string(),
+        })
+        .collect()
+}
+
+// How alike two names are from 0 to 1.  Each word of the shorter name is paired
+// with its closest word in the other, so the order of the words doesn't matter,
+// and words missing from the shorter name count against it.
+pub fn name_similarity(a: &str, b: &str) -> f64 {
+    let (a, b) = (normalize_name(a), normalize_name(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let (short, long) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    let closest: f64 = short
+        .iter()
+        .map(|word| {
+            long.iter()
+                .map(|other| strsim::jaro_winkler(word, other))
+                .fold(0.0, f64::max)
+        })
+        .sum();
+    let coverage = short.len() as f64 / long.len() as f64;
+    closest / short.len() as f64 * (0.7 + 0.3 * coverage)
+}
+
+// Whether two words are the same, or one is the initial of the other.
+fn same_word(a: &str, b: &str) -> bool {
+    let initial = |short: &str, long: &str| short.chars().count() == 1 && long.starts_with(short);
+    a == b || initial(a, b) || initial(b, a)
+}
+
+// A typo in the surname is tolerated but the first names have to agree, since
+// "Mark Smith" and "Mary Smith" are as alike as a misspelling and yet are
+// different people.  The first word of each name must appear in the other,
+// either in full or as an initial.
+fn first_names_agree(a: &[String], b: &[String]) -> bool {
+    let first_in = |words: &[String], other: &[String]| {
+        words
+            .first()
+            .is_some_and(|first| other.iter().any(|word| same_word(first, word)))
+    };
+    first_in(a, b) && first_in(b, a)
+}
+
+// The similarity of the names, or 0 when their first names don't agree.
+pub fn name_score(a: &str, b: &str) -> f64 {
+    if first_names_agree(&normalize_name(a), &normalize_name(b)) {
+        name_similarity(a, b)
+    } else {
+        0.0
+    }
+}
+
+pub fn same_name(a: &str, b: &str) -> bool {
+    name_score(a, b) >= NAME_THRESHOLD
+}
+
+pub fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+    if email.contains('@') {
+        Some(email)
+    } else {
+        None
+    }
+}
+
+pub fn same_email(a: &str, b: &str) -> bool {
+    match (normalize_email(a), normalize_email(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+// The last eight digits, which ignores spacing and whether the number was
+// written with the country code or a leading 0.
+pub fn normalize_phone(number: &str) -> Option<String> {
+    let digits: Vec<char> = number.chars().filter(char::is_ascii_digit).collect();
+    if digits.len() < 6 {
+        return None;
+    }
+    Some(digits[digits.len().saturating_sub(8)..].iter().collect())
+}
+
+fn tokens(text: &str) -> HashSet<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,150 @@
+use super::format::current_rms_date;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+// Opportunity states as reported by Current RMS, 3 is a confirmed order.
+pub const STATE_DRAFT: u32 = 1;
+pub const STATE_QUOTATION: u32 = 2;
+pub const STATE_ORDER: u32 = 3;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MemberEmail {
+    pub id: u64,
+    pub address: String,
+    #[serde(default)]
+    pub type_id: Option<u32>,
+    #[serde(default)]
+    pub email_type_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MemberPhone {
+    pub id: u64,
+    pub number: String,
+    #[serde(default)]
+    pub type_id: Option<u32>,
+    #[serde(default)]
+    pub phone_type_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Member {
+    pub id: u64,
+    pub name: String,
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default)]
+    pub emails: Vec<MemberEmail>,
+    #[serde(default)]
+    pub phones: Vec<MemberPhone>,
+}
+
+impl Member {
+    pub fn email_addresses(&self) -> impl Iterator<Item = &str> {
+        self.emails
+            .iter()
+            .map(|email| email.address.as_str())
+            .filter(|address| !address.is_empty())
+    }
+
+    pub fn primary_email(&self) -> Option<&str> {
+        self.email_addresses().next()
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Address {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub street: Option<String>,
+    #[serde(default)]
+    pub city: Option<String>,
+    #[serde(default)]
+    pub county: Option<String>,
+    #[serde(default)]
+    pub postcode: Option<String>,
+    #[serde(default)]
+    pub country_name: Option<String>,
+}
+
+// Formats the address as "street,city,county,postcode", which is what we send to
+// the geocoding service.
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let part = |value: &Option<String>| value.clone().unwrap_or_default();
+        write!(
+            f,
+            "{},{},{},{}",
+            part(&self.street),
+            part(&self.city),
+            part(&self.county),
+            part(&self.postcode)
+        )
+    }
+}
+
+impl Address {
+    // The parts which are filled in separated by commas, for showing to people.
+    pub fn one_line(&self) -> String {
+        [&self.street, &self.city, &self.county, &self.postcode]
+            .iter()
+            .filter_map(|part| part.as_deref())
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Destination {
+    #[serde(default)]
+    pub address: Option<Address>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Opportunity {
+    pub id: u64,
+    #[serde(default)]
+    pub member_id: Option<u64>,
+    #[serde(default)]
+    pub member: Option<Member>,
+    #[serde(default)]
+    pub subject: String,
+    #[serde(with = "current_rms_date")]
+    pub starts_at: DateTime<Utc>,
+    #[serde(with = "current_rms_date")]
+    pub ends_at: DateTime<Utc>,
+    // When the delivery and collection are booked for, if they have been.
+    #[serde(default, with = "current_rms_date::option")]
+    pub deliver_starts_at: Option<DateTime<Utc>>,
+    #[serde(default, with = "current_rms_date::option")]
+    pub collect_starts_at: Option<DateTime<Utc>>,
+    pub state: u32,
+    #[serde(default)]
+    pub state_name: String,
+    #[serde(default)]
+    pub status: u32,
+    #[serde(default)]
+    pub status_name: String,
+    #[serde(default)]
+    pub destination: Option<Destination>,
+    #[serde(default)]
+    pub custom_fields: Map<String, Value>,
+    // When the opportunity was last changed.
+    #[serde(default, with = "current_rms_date::option")]
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Opportunity {
+    pub fn is_confirmed(&self) -> bool {
+        self.state == STATE_ORDER
+    }
+
+    pub fn address(&self) -> Option<&Address> {
+        self.destination.as_ref()?.address.as_ref()
+    }
+}
@@ -0,0 +1,150 @@
+// Serde helpers for the field formats used by the two services.
+//
+// DateTime supports serde out of the box but only for RFC3339, which is fine for
+// reading Current RMS but not for ServiceM8, which uses '%Y-%m-%d %H:%M:%S' and
+// represents an unset date as either an empty string or '0000-00-00 00:00:00'.
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+
+// Current RMS uses RFC3339 with millisecond precision, ie "2021-03-22T00:00:00.000Z".
+pub mod current_rms_date {
+    use super::*;
+    use serde::{self, Deserialize, Deserializer, Serializer};
+
+    pub const FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3fZ";
+
+    pub fn parse(s: &str) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|date| date.with_timezone(&Utc))
+    }
+
+    pub fn serialize<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&date.format(FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse(&s).ok_or_else(|| serde::de::Error::custom(format!("invalid date '{}'", s)))
+    }
+
+    // For the dates which Current RMS leaves as null until they are set.
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match date {
+                Some(date) => super::serialize(date, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            match Option::<String>::deserialize(deserializer)? {
+                Some(s) if !s.is_empty() => parse(&s)
+                    .map(Some)
+                    .ok_or_else(|| serde::de::Error::custom(format!("invalid date '{}'", s))),
+                _ => Ok(None),
+            }
+        }
+    }
+}
+
+// ServiceM8 uses "%Y-%m-%d %H:%M:%S" in UTC.
+pub mod servicem8_date {
+    use super::*;
+    use serde::{self, Deserialize, Deserializer, Serializer};
+
+    pub const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+    pub fn parse(s: &str) -> Option<DateTime<Utc>> {
+        NaiveDateTime::parse_from_str(s, FORMAT)
+            .ok()
+            .map(|date| Utc.from_utc_datetime(&date))
+    }
+
+    pub fn serialize<S>(date: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&date.format(FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Utc>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        parse(&s).ok_or_else(|| serde::de::Error::custom(format!("invalid date '{}'", s)))
+    }
+
+    // Unset dates come back as "" or "0000-00-00 00:00:00", both of which map to None.
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S>(date: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match date {
+                Some(date) => super::serialize(date, serializer),
+                None => serializer.serialize_str(""),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            let s = Option::<String>::deserialize(deserializer)?.unwrap_or_default();
+            if s.is_empty() || s.starts_with("0000-00-00") {
+                return Ok(None);
+            }
+            parse(&s)
+                .map(Some)
+                .ok_or_else(|| serde::de::Error::custom(format!("invalid date '{}'", s)))
+        }
+    }
+}
+
+// ServiceM8 flags such as `active` and `is_individual` are 0/1 but aren't always
+// returned as numbers, so accept numbers, booleans and numeric strings.
+pub mod servicem8_flag {
+    use serde::{self, Deserialize, Deserializer, Serializer};
+    use serde_json::Value;
+
+    pub fn serialize<S>(flag: &bool, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u8(u8::from(*flag))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<bool, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        match Value::deserialize(deserializer)? {
+            Value::Bool(b) => Ok(b),
+            Value::Number(n) => Ok(n.as_i64().unwrap_or(0) != 0),
+            Value::String(s) => Ok(!s.trim().is_empty() && s.trim() != "0"),
+            Value::Null => Ok(false),
+            other => Err(serde::de::Error::custom(format!(
+                "expected a 0/1 flag, found {}",
+                other
+            ))),
+        }
+    }
+}
@@ -0,0 +1,15 @@
+// Typed versions of the records returned by Current RMS and ServiceM8.
+//
+// Only the fields that the tools make use of are modelled, anything else in
+// the response is ignored during deserialization.
+
+pub mod format;
+
+mod current_rms;
+mod servicem8;
+
+pub use self::current_rms::{
+    Address, Destination, Member, MemberEmail, MemberPhone, Opportunity, STATE_DRAFT, STATE_ORDER,
+    STATE_QUOTATION,
+};
+pub use self::servicem8::{Company, Job, JobActivity, JobContact};
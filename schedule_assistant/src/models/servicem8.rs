@@ -0,0 +1,143 @@
+use super::format::{servicem8_date, servicem8_flag};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+fn active_default() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Company {
+    pub uuid: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, with = "servicem8_flag")]
+    pub is_individual: bool,
+    #[serde(default = "active_default", with = "servicem8_flag")]
+    pub active: bool,
+    #[serde(default)]
+    pub address: String,
+    #[serde(default)]
+    pub address_street: String,
+    #[serde(default)]
+    pub address_city: String,
+    #[serde(default)]
+    pub address_postcode: String,
+    // When the record was last changed, every table has one.
+    #[serde(default, with = "servicem8_date::option")]
+    pub edit_date: Option<DateTime<Utc>>,
+}
+
+impl Company {
+    // Splits the company name into its parts.  Individuals are stored either as
+    // "lastname, firstname" or "firstname lastname", while a business is left whole.
+    pub fn name_parts(&self) -> Vec<String> {
+        if !self.is_individual {
+            return vec![self.name.clone()];
+        }
+
+        let mut name = self.name.clone();
+        match name.find(',') {
+            // name is structured "lastname, firstname"
+            Some(c) => {
+                let last: String = name.drain(..c).collect();
+                let first = name[1..].trim().to_string();
+                vec![first, last]
+            }
+            // name either has no surname or is structured "firstname lastname"
+            None => {
+                let c = name.find(' ').unwrap_or(name.len());
+                let first: String = name.drain(..c).collect();
+                let last = name.trim().to_string();
+                if last.is_empty() {
+                    vec![first]
+                } else {
+                    vec![first, last]
+                }
+            }
+        }
+    }
+
+    // The name of the company as it would be written, ie "firstname lastname".
+    pub fn display_name(&self) -> String {
+        self.name_parts().join(" ").trim().to_string()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Job {
+    pub uuid: String,
+    #[serde(default = "active_default", with = "servicem8_flag")]
+    pub active: bool,
+    #[serde(default)]
+    pub company_uuid: String,
+    #[serde(default)]
+    pub job_address: String,
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub generated_job_id: String,
+    #[serde(default)]
+    pub purchase_order_number: String,
+    #[serde(default)]
+    pub job_description: String,
+    #[serde(default, with = "servicem8_date::option")]
+    pub date: Option<DateTime<Utc>>,
+    #[serde(default, with = "servicem8_date::option")]
+    pub edit_date: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JobActivity {
+    pub uuid: String,
+    #[serde(default = "active_default", with = "servicem8_flag")]
+    pub active: bool,
+    #[serde(default)]
+    pub job_uuid: String,
+    #[serde(default)]
+    pub staff_uuid: String,
+    #[serde(default, with = "servicem8_date::option")]
+    pub start_date: Option<DateTime<Utc>>,
+    #[serde(default, with = "servicem8_date::option")]
+    pub end_date: Option<DateTime<Utc>>,
+    #[serde(default, with = "servicem8_flag")]
+    pub activity_was_scheduled: bool,
+    #[serde(default, with = "servicem8_date::option")]
+    pub edit_date: Option<DateTime<Utc>>,
+}
+
+impl JobActivity {
+    // The (start, end) of the activity if it has been given both dates.
+    pub fn period(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        Some((self.start_date?, self.end_date?))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JobContact {
+    pub uuid: String,
+    #[serde(default = "active_default", with = "servicem8_flag")]
+    pub active: bool,
+    #[serde(default)]
+    pub job_uuid: String,
+    #[serde(default)]
+    pub first: String,
+    #[serde(default)]
+    pub last: String,
+    #[serde(default)]
+    pub email: String,
+    #[serde(default)]
+    pub phone: String,
+    #[serde(default)]
+    pub mobile: String,
+    #[serde(default, rename = "type")]
+    pub contact_type: String,
+    #[serde(default, with = "servicem8_date::option")]
+    pub edit_date: Option<DateTime<Utc>>,
+}
+
+impl JobContact {
+    pub fn full_name(&self) -> String {
+        format!("{} {}", self.first, self.last).trim().to_string()
+    }
+}
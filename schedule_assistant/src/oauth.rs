@@ -0,0 +1,332 @@
+// OAuth2 authorization against Current RMS.
+//
+// Tokens are stored on disk once they have been issued, reused until they
+// expire and then refreshed using the refresh token.  The interactive browser
+// flow is only used when there is no stored token that can be refreshed.
+
+use crate::authentication::env_var;
+use crate::error::{Error, Result};
+use chrono::{DateTime, Duration, Utc};
+use oauth2::basic::{BasicClient, BasicTokenResponse};
+use oauth2::reqwest::http_client;
+use oauth2::{
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, RefreshToken,
+    TokenResponse, TokenUrl,
+};
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration as StdDuration, Instant};
+use std::{env, fs, thread};
+use url::Url;
+
+static DEFAULT_TOKEN_FILE: &str = ".current_rms_token.json";
+static DEFAULT_REDIRECT_URL: &str = "http://localhost:8080";
+const DEFAULT_AUTHORIZATION_TIMEOUT_SECONDS: u64 = 300;
+const ACCEPT_POLL_INTERVAL: StdDuration = StdDuration::from_millis(100);
+const REQUEST_READ_TIMEOUT: StdDuration = StdDuration::from_secs(5);
+
+// Treat tokens as expired slightly early so that they don't lapse mid-run.
+const EXPIRY_MARGIN_SECONDS: i64 = 60;
+
+#[derive(Deserialize, Serialize)]
+struct StoredToken {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl StoredToken {
+    fn from_response(response: &BasicTokenResponse, previous: Option<&StoredToken>) -> Self {
+        let expires_at = response
+            .expires_in()
+            .and_then(|expires_in| Duration::from_std(expires_in).ok())
+            .map(|expires_in| Utc::now() + expires_in);
+        // The refresh grant doesn't have to issue a new refresh token, in which
+        // case the previous one remains valid.
+        let refresh_token = response
+            .refresh_token()
+            .map(|token| token.secret().clone())
+            .or_else(|| previous.and_then(|token| token.refresh_token.clone()));
+
+        StoredToken {
+            access_token: response.access_token().secret().clone(),
+            refresh_token,
+            expires_at,
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => Utc::now() + Duration::seconds(EXPIRY_MARGIN_SECONDS) < expires_at,
+            None => true,
+        }
+    }
+}
+
+// Location of the token file, overridable with CURRENT_TOKEN_FILE.
+fn token_path() -> PathBuf {
+    env::var("CURRENT_TOKEN_FILE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(DEFAULT_TOKEN_FILE))
+}
+
+fn load(path: &Path) -> Option<StoredToken> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(token) => Some(token),
+        Err(e) => {
+            println!("Ignoring unreadable token file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn save(path: &Path, token: &StoredToken) -> Result<()> {
+    let contents = serde_json::to_string_pretty(token)?;
+
+    // The file holds credentials so keep it private to the current user, from
+    // the moment it is created.  A file left by an older version is tightened
+    // up as well since the mode only applies to new files.
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).or_else(|e| {
+            if e.kind() == io::ErrorKind::NotFound {
+                Ok(())
+            } else {
+                Err(e)
+            }
+        })?;
+    }
+    options.open(path)?.write_all(contents.as_bytes())?;
+
+    Ok(())
+}
+
+// Where the authorization server sends the user back to, and how long we wait
+// for that to happen.
+struct RedirectSettings {
+    // Kept as configured since it has to match the registered URL exactly.
+    redirect_url: String,
+    url: Url,
+    port: u16,
+    timeout: StdDuration,
+}
+
+impl RedirectSettings {
+    // CURRENT_REDIRECT_URL must match the redirect URL registered with Current RMS,
+    // CURRENT_REDIRECT_PORT defaults to the port of that URL, and the listener gives
+    // up after CURRENT_AUTHORIZATION_TIMEOUT seconds.
+    fn from_env() -> Result<Self> {
+        let redirect_url =
+            env::var("CURRENT_REDIRECT_URL").unwrap_or_else(|_| DEFAULT_REDIRECT_URL.to_string());
+        let url = Url::parse(&redirect_url)
+            .map_err(|e| Error::Configuration(format!("invalid CURRENT_REDIRECT_URL: {}", e)))?;
+        let port = match env::var("CURRENT_REDIRECT_PORT") {
+            Ok(port) => port.parse().map_err(|_| {
+                Error::Configuration(format!("invalid CURRENT_REDIRECT_PORT '{}'", port))
+            })?,
+            Err(_) => url.port_or_known_default().unwrap_or(8080),
+        };
+        let timeout = match env::var("CURRENT_AUTHORIZATION_TIMEOUT") {
+            Ok(seconds) => seconds.parse().map_err(|_| {
+                Error::Configuration(format!(
+                    "invalid CURRENT_AUTHORIZATION_TIMEOUT '{}'",
+                    seconds
+                ))
+            })?,
+            Err(_) => DEFAULT_AUTHORIZATION_TIMEOUT_SECONDS,
+        };
+
+        Ok(RedirectSettings {
+            redirect_url,
+            url,
+            port,
+            timeout: StdDuration::from_secs(timeout),
+        })
+    }
+}
+
+fn client(web_url: &str, redirect: &RedirectSettings) -> Result<BasicClient> {
+    let client_id = ClientId::new(env_var("CURRENT_CLIENT_ID")?);
+    let client_secret = ClientSecret::new(env_var("CURRENT_CLIENT_SECRET")?);
+
+    let auth_url = AuthUrl::new(format!("{}/oauth2/authorize", web_url))
+        .map_err(|e| Error::Configuration(format!("invalid authorization endpoint URL: {}", e)))?;
+    let token_url = TokenUrl::new(format!("{}/oauth2/token", web_url))
+        .map_err(|e| Error::Configuration(format!("invalid token endpoint URL: {}", e)))?;
+    let redirect_url = RedirectUrl::new(redirect.redirect_url.clone())
+        .map_err(|e| Error::Configuration(format!("invalid redirect URL: {}", e)))?;
+
+    Ok(
+        BasicClient::new(client_id, Some(client_secret), auth_url, Some(token_url))
+            .set_redirect_url(redirect_url),
+    )
+}
+
+// Returns an access token for Current RMS, using the stored token where possible.
+// The web_url is that of the account, ie https://{subdomain}.current-rms.com.
+pub fn access_token(web_url: &str) -> Result<String> {
+    let redirect = RedirectSettings::from_env()?;
+    let client = client(web_url, &redirect)?;
+    let path = token_path();
+    let stored = load(&path);
+
+    if let Some(token) = &stored {
+        if token.is_valid() {
+            return Ok(token.access_token.clone());
+        }
+
+        if let Some(refresh_token) = &token.refresh_token {
+            match refresh(&client, refresh_token) {
+                Ok(response) => {
+                    let token = StoredToken::from_response(&response, stored.as_ref());
+                    save(&path, &token)?;
+                    return Ok(token.access_token);
+                }
+                Err(e) => println!("Unable to refresh the Current RMS token, {}", e),
+            }
+        }
+    }
+
+    let response = authorize(&client, &redirect)?;
+    let token = StoredToken::from_response(&response, None);
+    save(&path, &token)?;
+    Ok(token.access_token)
+}
+
+fn refresh(client: &BasicClient, refresh_token: &str) -> Result<BasicTokenResponse> {
+    client
+        .exchange_refresh_token(&RefreshToken::new(refresh_token.to_string()))
+        .request(http_client)
+        .map_err(|e| Error::Authentication(format!("unable to refresh the token: {}", e)))
+}
+
+// What the browser brought back to the redirect listener.
+enum Redirect {
+    Code { code: String, state: String },
+    Denied(String),
+    // Anything else the browser asks for, ie /favicon.ico.
+    Unrelated,
+}
+
+fn read_redirect(stream: &TcpStream, redirect_path: &str) -> Result<Redirect> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let target = request_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| Error::Authentication(String::from("malformed request line")))?;
+    let url = Url::parse(&format!("http://localhost{}", target))
+        .map_err(|e| Error::Authentication(format!("malformed request target: {}", e)))?;
+    if url.path() != redirect_path {
+        return Ok(Redirect::Unrelated);
+    }
+
+    let query_value = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    if let Some(error) = query_value("error") {
+        let description = query_value("error_description").unwrap_or_default();
+        return Ok(Redirect::Denied(
+            format!("{} {}", error, description).trim().to_string(),
+        ));
+    }
+
+    match (query_value("code"), query_value("state")) {
+        (Some(code), Some(state)) => Ok(Redirect::Code { code, state }),
+        _ => Ok(Redirect::Unrelated),
+    }
+}
+
+fn respond(mut stream: &TcpStream, status: &str, message: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\ncontent-type: text/plain\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        status,
+        message.len(),
+        message
+    );
+    // The browser going away doesn't affect the outcome, so ignore failures here.
+    let _ = stream.write_all(response.as_bytes());
+}
+
+// The interactive authorization code flow, the user is asked to open the
+// authorization URL in their browser which then redirects back to us.
+fn authorize(client: &BasicClient, redirect: &RedirectSettings) -> Result<BasicTokenResponse> {
+    // Generate the authorization URL to which we'll redirect the user.
+    let (authorize_url, csrf_state) = client.authorize_url(CsrfToken::new_random).url();
+
+    let listener = TcpListener::bind(("127.0.0.1", redirect.port))?;
+    listener.set_nonblocking(true)?;
+
+    println!("Open this URL in your browser:\n{}\n", authorize_url);
+
+    // Keep serving requests until the redirect arrives or we run out of time.
+    let deadline = Instant::now() + redirect.timeout;
+    let code = loop {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(Error::Authentication(format!(
+                        "timed out after {} seconds waiting for the authorization redirect",
+                        redirect.timeout.as_secs()
+                    )));
+                }
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_READ_TIMEOUT))?;
+
+        match read_redirect(&stream, redirect.url.path()) {
+            Ok(Redirect::Code { code, state }) => {
+                if state != *csrf_state.secret() {
+                    respond(
+                        &stream,
+                        "400 Bad Request",
+                        "Authorization failed, please try again.",
+                    );
+                    return Err(Error::Authentication(String::from(
+                        "the state returned with the authorization code doesn't match the one sent",
+                    )));
+                }
+                respond(&stream, "200 OK", "Go back to your terminal :)");
+                break AuthorizationCode::new(code);
+            }
+            Ok(Redirect::Denied(reason)) => {
+                respond(&stream, "200 OK", "Authorization was declined.");
+                return Err(Error::Authentication(format!(
+                    "authorization was declined: {}",
+                    reason
+                )));
+            }
+            Ok(Redirect::Unrelated) => respond(&stream, "404 Not Found", ""),
+            Err(e) => {
+                println!(
+                    "Ignoring a malformed request on the redirect listener, {}",
+                    e
+                );
+                respond(&stream, "400 Bad Request", "");
+            }
+        }
+    };
+
+    // Exchange the code with a token.
+    client
+        .exchange_code(code)
+        .request(http_client)
+        .map_err(|e| Error::Authentication(format!("unable to exchange the code: {}", e)))
+}
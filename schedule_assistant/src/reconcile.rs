@@ -0,0 +1,122 @@
+// Finds the ServiceM8 activities which no longer line up with their Current RMS
+// booking, usually because the event was moved in Current RMS and the job's
+// activities were left where they were.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use std::fmt;
+
+use crate::models::{JobActivity, Opportunity};
+use crate::Leg;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Drift {
+    // The delivery finishes after the event has started.
+    LateDelivery,
+    // The collection starts before the event has finished.
+    EarlyCollection,
+    // On a different day to the one booked in Current RMS.
+    WrongDay,
+}
+
+// An activity that has to be moved to the expected day.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reschedule {
+    pub opportunity_id: u64,
+    pub job_uuid: String,
+    pub activity_uuid: String,
+    pub leg: Leg,
+    pub scheduled: DateTime<Utc>,
+    pub expected: NaiveDate,
+    pub drift: Drift,
+}
+
+impl fmt::Display for Reschedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let leg = match self.leg {
+            Leg::Delivery => "delivery",
+            Leg::Collection => "collection",
+        };
+        let problem = match self.drift {
+            Drift::LateDelivery => "finishes after the event starts",
+            Drift::EarlyCollection => "starts before the event ends",
+            Drift::WrongDay => "is on the wrong day",
+        };
+        write!(
+            f,
+            "opportunity {}: the {} on {} {}, move it to {}",
+            self.opportunity_id,
+            leg,
+            self.scheduled.format("%Y-%m-%d %H:%M"),
+            problem,
+            self.expected
+        )
+    }
+}
+
+// The days that the delivery and collection are booked for, the start and end
+// of the event when they haven't been booked separately.
+pub fn expected_day(opportunity: &Opportunity, leg: Leg) -> NaiveDate {
+    match leg {
+        Leg::Delivery => opportunity
+            .deliver_starts_at
+            .unwrap_or(opportunity.starts_at),
+        Leg::Collection => opportunity.collect_starts_at.unwrap_or(opportunity.ends_at),
+    }
+    .date_naive()
+}
+
+// Compares the activities of the job linked to the opportunity against it.  The
+// earliest activity is taken to be the delivery and the latest the collection,
+// a job with only one activity has it taken as whichever end of the event it
+// is closest to.
+pub fn reconcile_job(
+    opportunity: &Opportunity,
+    job_uuid: &str,
+    activities: &[&JobActivity],
+) -> Vec<Reschedule> {
+    let mut scheduled: Vec<(&JobActivity, DateTime<Utc>, DateTime<Utc>)> = activities
+        .iter()
+        .filter(|activity| activity.active)
+        .filter_map(|&activity| {
+            let (start, end) = activity.period()?;
+            Some((activity, start, end))
+        })
+        .collect();
+    scheduled.sort_by_key(|&(_, start, _)| start);
+
+    let legs = match scheduled.as_slice() {
+        [] => Vec::new(),
+        [only] => {
+            let to_start = (only.1 - opportunity.starts_at).num_seconds().abs();
+            let to_end = (only.1 - opportunity.ends_at).num_seconds().abs();
+            let leg = if to_start <= to_end {
+                Leg::Delivery
+            } else {
+                Leg::Collection
+            };
+            vec![(leg, *only)]
+        }
+        [first, .., last] => vec![(Leg::Delivery, *first), (Leg::Collection, *last)],
+    };
+
+    legs.into_iter()
+        .filter_map(|(leg, (activity, start, end))| {
+            let expected = expected_day(opportunity, leg);
+            let drift = match leg {
+                Leg::Delivery if end > opportunity.starts_at => Drift::LateDelivery,
+                Leg::Collection if start < opportunity.ends_at => Drift::EarlyCollection,
+                _ if start.date_naive() != expected => Drift::WrongDay,
+                _ => return None,
+            };
+            Some(Reschedule {
+                opportunity_id: opportunity.id,
+                job_uuid: job_uuid.to_string(),
+                activity_uuid: activity.uuid.clone(),
+                leg,
+                scheduled: start,
+                expected,
+                drift,
+            })
+        })
+        .collect()
+}
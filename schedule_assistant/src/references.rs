@@ -0,0 +1,141 @@
+// References between an opportunity and its job which are stored in the records
+// themselves, so that once a match has been made every tool can find it without
+// matching again.
+//
+// The job's uuid is kept in a custom field of the opportunity, named by
+// CURRENT_JOB_UUID_FIELD (servicem8_job_uuid by default), and the opportunity's
+// number is kept in the job as "RMS#1234".  SERVICEM8_OPPORTUNITY_FIELD chooses
+// whether that goes in the purchase_order_number (the default) or is added to
+// the end of the job_description.
+
+use crate::authentication::AuthenticationCache;
+use crate::error::{Error, Result};
+use crate::models::{Job, Opportunity};
+use crate::{current_rms, servicem8};
+use serde_json::{json, Value};
+use std::env;
+
+pub static OPPORTUNITY_PREFIX: &str = "RMS#";
+static DEFAULT_JOB_UUID_FIELD: &str = "servicem8_job_uuid";
+static DEFAULT_OPPORTUNITY_FIELD: &str = "purchase_order_number";
+
+pub fn job_uuid_field() -> String {
+    env::var("CURRENT_JOB_UUID_FIELD").unwrap_or_else(|_| DEFAULT_JOB_UUID_FIELD.to_string())
+}
+
+// The uuid of the job stored against the opportunity.
+pub fn stored_job(opportunity: &Opportunity) -> Option<&str> {
+    opportunity
+        .custom_fields
+        .get(&job_uuid_field())?
+        .as_str()
+        .map(str::trim)
+        .filter(|uuid| !uuid.is_empty())
+}
+
+// The number of the opportunity stored against the job, from either field.
+pub fn stored_opportunity(job: &Job) -> Option<u64> {
+    [&job.purchase_order_number, &job.job_description]
+        .iter()
+        .find_map(|field| parse_reference(field))
+}
+
+// Whether the opportunity and the job already refer to each other, so there is
+// nothing to write back.
+pub fn refer_to_each_other(opportunity: &Opportunity, job: &Job) -> bool {
+    stored_job(opportunity) == Some(job.uuid.as_str())
+        && stored_opportunity(job) == Some(opportunity.id)
+}
+
+fn parse_reference(text: &str) -> Option<u64> {
+    let start = text.find(OPPORTUNITY_PREFIX)? + OPPORTUNITY_PREFIX.len();
+    let digits: String = text[start..]
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().ok()
+}
+
+pub fn reference_for(opportunity_id: u64) -> String {
+    format!("{}{}", OPPORTUNITY_PREFIX, opportunity_id)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OpportunityField {
+    PurchaseOrderNumber,
+    JobDescription,
+}
+
+// The field of the job which the opportunity's number is kept in.
+fn opportunity_field() -> Result<OpportunityField> {
+    let field = env::var("SERVICEM8_OPPORTUNITY_FIELD")
+        .unwrap_or_else(|_| DEFAULT_OPPORTUNITY_FIELD.to_string());
+    match field.as_str() {
+        "purchase_order_number" => Ok(OpportunityField::PurchaseOrderNumber),
+        "job_description" => Ok(OpportunityField::JobDescription),
+        _ => Err(Error::Configuration(format!(
+            "unknown SERVICEM8_OPPORTUNITY_FIELD '{}', expected 'purchase_order_number' or 'job_description'",
+            field
+        ))),
+    }
+}
+
+fn append_reference(description: &str, reference: String) -> String {
+    let description = description.trim_end();
+    if description.is_empty() {
+        reference
+    } else {
+        format!("{}\n{}", description, reference)
+    }
+}
+
+// The description and reference of a job created for the opportunity, so that
+// it refers to the opportunity from the start.
+pub fn new_job_fields(opportunity: &Opportunity) -> Result<Value> {
+    let reference = reference_for(opportunity.id);
+    Ok(match opportunity_field()? {
+        OpportunityField::PurchaseOrderNumber => json!({
+            "purchase_order_number": reference,
+            "job_description": opportunity.subject,
+        }),
+        OpportunityField::JobDescription => json!({
+            "job_description": append_reference(&opportunity.subject, reference),
+        }),
+    })
+}
+
+// Stores the job against the opportunity and the opportunity against the job,
+// skipping either side which already refers to the other.
+pub fn write_back(
+    auth_cache: &AuthenticationCache,
+    opportunity: &Opportunity,
+    job: &Job,
+) -> Result<()> {
+    if stored_job(opportunity) != Some(job.uuid.as_str()) {
+        let fields = json!({ "custom_fields": { job_uuid_field(): job.uuid } });
+        current_rms::update_opportunity(auth_cache, opportunity.id, &fields)?;
+    }
+
+    if stored_opportunity(job) == Some(opportunity.id) {
+        return Ok(());
+    }
+    let reference = reference_for(opportunity.id);
+    let fields = match opportunity_field()? {
+        OpportunityField::PurchaseOrderNumber => {
+            // Never overwrite a purchase order number entered by the customer.
+            if !job.purchase_order_number.trim().is_empty()
+                && parse_reference(&job.purchase_order_number).is_none()
+            {
+                return Err(Error::DataIntegrity(format!(
+                    "job {} already has the purchase order number '{}'",
+                    job.uuid, job.purchase_order_number
+                )));
+            }
+            json!({ "purchase_order_number": reference })
+        }
+        OpportunityField::JobDescription => {
+            json!({ "job_description": append_reference(&job.job_description, reference) })
+        }
+    };
+    servicem8::update_record(auth_cache, servicem8::Table::Job, &job.uuid, &fields)
+}
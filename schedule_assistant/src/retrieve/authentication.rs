@@ -1,6 +1,5 @@
 //@todo: Create a trait to define the endpoint?
-use reqwest::blocking::RequestBuilder;
-
+use reqwest::RequestBuilder;
 
 pub trait Authentication {
     fn apply(&self, request_builder: RequestBuilder) -> RequestBuilder;
@@ -26,7 +25,7 @@ impl Authentication for SubdomainAuthentication {
     fn apply(&self, request_builder: RequestBuilder) -> RequestBuilder {
         request_builder
             .header("X-SUBDOMAIN", self.subdomain.clone())
-            .header("X-AUTH-TOKEN", self.password.clone())        
+            .header("X-AUTH-TOKEN", self.password.clone())
     }
 }
 
@@ -41,4 +40,23 @@ impl Authentication for BearerAuthentication {
             .header("X-SUBDOMAIN", &self.subdomain)
             .bearer_auth(&self.token)
     }
-}
\ No newline at end of file
+}
+
+// Current RMS accepts either a static API key or an OAuth bearer token.
+pub enum CurrentRmsAuthentication {
+    ApiKey(SubdomainAuthentication),
+    OAuth(BearerAuthentication),
+}
+
+impl Authentication for CurrentRmsAuthentication {
+    fn apply(&self, request_builder: RequestBuilder) -> RequestBuilder {
+        match self {
+            CurrentRmsAuthentication::ApiKey(authentication) => {
+                authentication.apply(request_builder)
+            }
+            CurrentRmsAuthentication::OAuth(authentication) => {
+                authentication.apply(request_builder)
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+// Records the responses to every request into a cassette on disk, or answers
+// requests from a previously recorded cassette without touching the network,
+// which lets a run against the real services be repeated exactly.
+//
+// HTTP_CASSETTE       the file to record to or replay from
+// HTTP_CASSETTE_MODE  either "record" or "replay" (the default)
+// HTTP_CASSETTE_SALT  mixed into the pseudonyms when recording, keep it secret
+//                     when recording the real services
+//
+// Only the status, a few headers and JSON bodies are kept.  Request headers,
+// which hold the credentials, are never written and personal details within the
+// bodies are replaced with pseudonyms (see scrub.rs).
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use url::Url;
+
+use super::scrub::Scrubber;
+use crate::error::{Error, Result};
+
+// The response headers that the tools read, anything else is dropped.
+static KEPT_HEADERS: &[&str] = &["content-type", "content-disposition", "x-record-uuid"];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    // The path and query only, so that a cassette can be replayed against
+    // whichever host the services are configured at.
+    path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request: Option<Value>,
+    status: u16,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    // Missing when the body wasn't JSON, ie a PDF.
+    #[serde(default)]
+    body: Option<Value>,
+    #[serde(skip)]
+    played: bool,
+}
+
+struct State {
+    interactions: Vec<Interaction>,
+    scrubber: Scrubber,
+}
+
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+    state: Mutex<State>,
+}
+
+impl Cassette {
+    // Starts a new recording, replacing anything already at path.
+    pub fn record(path: &Path) -> Cassette {
+        Cassette::with(path, Mode::Record, Vec::new())
+    }
+
+    pub fn replay(path: &Path) -> Result<Cassette> {
+        let file = fs::read(path)?;
+        let interactions = serde_json::from_slice(&file)?;
+        Ok(Cassette::with(path, Mode::Replay, interactions))
+    }
+
+    pub fn from_env() -> Result<Option<Cassette>> {
+        let path = match env::var("HTTP_CASSETTE") {
+            Ok(path) => PathBuf::from(path),
+            Err(_) => return Ok(None),
+        };
+        let mode = env::var("HTTP_CASSETTE_MODE").unwrap_or_else(|_| String::from("replay"));
+        match mode.to_lowercase().as_str() {
+            "record" => Ok(Some(Cassette::record(&path))),
+            "replay" => Cassette::replay(&path).map(Some),
+            _ => Err(Error::Configuration(format!(
+                "unknown HTTP_CASSETTE_MODE '{}', expected 'record' or 'replay'",
+                mode
+            ))),
+        }
+    }
+
+    fn with(path: &Path, mode: Mode, interactions: Vec<Interaction>) -> Cassette {
+        Cassette {
+            path: path.to_path_buf(),
+            mode,
+            state: Mutex::new(State {
+                interactions,
+                scrubber: Scrubber::new(&env::var("HTTP_CASSETTE_SALT").unwrap_or_default()),
+            }),
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // The first recorded response to the same request that hasn't already been
+    // used, so that a repeated request gets each of its responses in turn.
+    pub fn play(&self, method: &Method, url: &str) -> Result<Response> {
+        let path = path_of(url);
+        let mut state = self.state();
+        let interaction = state
+            .interactions
+            .iter_mut()
+            .find(|i| !i.played && i.method == method.as_str() && i.path == path)
+            .ok_or_else(|| Error::Replay(format!("{} {}", method, path)))?;
+        interaction.played = true;
+        Ok(response(interaction))
+    }
+
+    // Saves the response, the body has to be read to do so and a copy of the
+    // response is returned in its place.
+    pub async fn capture<B: Serialize>(
+        &self,
+        method: &Method,
+        url: &str,
+        request: Option<&B>,
+        response: Response,
+    ) -> Result<Response> {
+        let status = response.status().as_u16();
+        let headers: Vec<(String, String)> = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| KEPT_HEADERS.contains(&name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let bytes = response.bytes().await?;
+
+        // The caller sees the real body rather than the scrubbed one.
+        let mut copy = response_from(status, &headers);
+        *copy.body_mut() = bytes.to_vec();
+
+        let mut state = self.state();
+        let mut request = match request {
+            Some(request) => Some(serde_json::to_value(request)?),
+            None => None,
+        };
+        let mut body = serde_json::from_slice::<Value>(&bytes).ok();
+        for value in request.iter_mut().chain(body.iter_mut()) {
+            state.scrubber.scrub(value);
+        }
+        state.interactions.push(Interaction {
+            method: method.to_string(),
+            path: path_of(url),
+            request,
+            status,
+            headers,
+            body,
+            played: false,
+        });
+        fs::write(&self.path, serde_json::to_vec_pretty(&state.interactions)?)?;
+        Ok(copy.into())
+    }
+
+    // The requests which were recorded but haven't been replayed.
+    pub fn unplayed(&self) -> Vec<String> {
+        self.state()
+            .interactions
+            .iter()
+            .filter(|i| !i.played)
+            .map(|i| format!("{} {}", i.method, i.path))
+            .collect()
+    }
+}
+
+fn path_of(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(url) => match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        },
+        Err(_) => url.to_string(),
+    }
+}
+
+fn response(interaction: &Interaction) -> Response {
+    let mut response = response_from(interaction.status, &interaction.headers);
+    if let Some(body) = &interaction.body {
+        *response.body_mut() = serde_json::to_vec(body).unwrap_or_default();
+    }
+    response.into()
+}
+
+fn response_from(status: u16, headers: &[(String, String)]) -> http::Response<Vec<u8>> {
+    let mut response = http::Response::new(Vec::new());
+    *response.status_mut() = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            map.append(name, value);
+        }
+    }
+    *response.headers_mut() = map;
+    response
+}
@@ -0,0 +1,90 @@
+// A long lived HTTP client which is shared by every request, so that
+// connections (and their TLS sessions) are pooled rather than renegotiated for
+// each page or table that we download.
+//
+// Requests are made asynchronously on a runtime owned by the client, the
+// blocking functions used by the binaries drive it with block_on.
+
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT};
+use reqwest::{Client, Method, RequestBuilder};
+use std::future::Future;
+use std::time::Duration;
+use tokio::runtime::{self, Runtime};
+
+use super::cassette::Cassette;
+use super::endpoint::Service;
+use super::rate_limit::RateLimiter;
+use crate::error::Result;
+
+static USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+pub struct ApiClient {
+    client: Client,
+    // Pooled connections belong to the runtime that opened them, so the same
+    // runtime has to be used for every request.
+    runtime: Runtime,
+    current_rms: RateLimiter,
+    servicem8: RateLimiter,
+    cassette: Option<Cassette>,
+}
+
+impl ApiClient {
+    pub fn new() -> Result<ApiClient> {
+        let runtime = runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+
+        let client = {
+            let _guard = runtime.enter();
+            Client::builder()
+                .user_agent(USER_AGENT)
+                .default_headers(headers)
+                .connect_timeout(CONNECT_TIMEOUT)
+                .timeout(REQUEST_TIMEOUT)
+                .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+                .build()?
+        };
+
+        Ok(ApiClient {
+            client,
+            runtime,
+            current_rms: RateLimiter::per_minute(60),
+            servicem8: RateLimiter::per_minute(180),
+            cassette: None,
+        })
+    }
+
+    // Records the responses to, or replays them instead of, every request.
+    pub fn with_cassette(mut self, cassette: Cassette) -> ApiClient {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    pub fn cassette(&self) -> Option<&Cassette> {
+        self.cassette.as_ref()
+    }
+
+    // Runs the requests to completion, this mustn't be called from within
+    // another future.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    // Waits until the service will accept another request.
+    pub async fn throttle(&self, service: Service) {
+        match service {
+            Service::CurrentRms => self.current_rms.wait().await,
+            Service::ServiceM8 => self.servicem8.wait().await,
+        }
+    }
+
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+}
@@ -2,47 +2,91 @@
 
 use url::Url;
 
+// The service that an endpoint belongs to, which decides the rate limit that
+// its requests count against wherever the service has been configured to be.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Service {
+    CurrentRms,
+    ServiceM8,
+}
+
 pub trait Endpoint {
     fn url(&self) -> String;
+    fn service(&self) -> Service;
 }
 
 pub struct BasicEndpoint {
+    pub service: Service,
     pub base_url: String,
+    // Query parameters, ie filters.
+    pub params: Vec<(String, String)>,
 }
 
 impl Endpoint for BasicEndpoint {
+    fn service(&self) -> Service {
+        self.service
+    }
+
     fn url(&self) -> String {
-        return self.base_url.clone();
+        if self.params.is_empty() {
+            return self.base_url.clone();
+        }
+
+        match Url::parse_with_params(&self.base_url, &self.params) {
+            Ok(url) => url.as_str().to_string(),
+            // As with PagedEndpoint, fall back to the unfiltered URL.
+            Err(_e) => self.base_url.clone(),
+        }
     }
 }
 
+// Number of records requested with each page unless told otherwise, this is
+// the most that Current RMS will return.
+pub const DEFAULT_PER_PAGE: u32 = 100;
+
+#[derive(Clone)]
 pub struct PagedEndpoint {
+    pub service: Service,
     pub base_url: String,
     pub page: u32,
+    pub per_page: u32,
+    // Additional query parameters sent with every page, ie filters.
+    pub params: Vec<(String, String)>,
 }
 
 impl PagedEndpoint {
     pub fn advance(&mut self) {
         self.page += 1;
     }
+
+    // The same request for another page.
+    pub fn at_page(&self, page: u32) -> PagedEndpoint {
+        PagedEndpoint {
+            page,
+            ..self.clone()
+        }
+    }
 }
 
 impl Endpoint for PagedEndpoint {
+    fn service(&self) -> Service {
+        self.service
+    }
+
     fn url(&self) -> String {
-        let per_page = "100";
-        
-        let params = vec![
+        let mut params = vec![
             ("page".to_string(), self.page.to_string()),
-            ("per_page".to_string(), per_page.to_string()),
+            ("per_page".to_string(), self.per_page.to_string()),
         ];
-    
+        params.extend(self.params.iter().cloned());
+
         match Url::parse_with_params(&self.base_url, params) {
             Ok(url) => url.as_str().to_string(),
-            // Not quite sure what to do if this call fails.  Returning the base_url 
+            // Not quite sure what to do if this call fails.  Returning the base_url
             // which should allow us to attempt to make the call without parameters,
-            // obviously this means that we wouldnt be paging but the option is 
+            // obviously this means that we wouldnt be paging but the option is
             // returning an Error or something.
-            Err(_e) => self.base_url.clone()  
+            Err(_e) => self.base_url.clone(),
         }
     }
-}
\ No newline at end of file
+}
@@ -1,79 +1,103 @@
 //@todo: Create a trait to define the endpoint?
 
-use reqwest::blocking::{Client, Response};
-//use reqwest::header::*; //< for header.CONTENT_DISPOSITION
-use serde_json::Value;
+// Blocking versions of the requests in fetch_async for the binaries, each call
+// runs on the runtime owned by the ApiClient.
+
+use reqwest::header::HeaderMap;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
 use super::authentication::Authentication;
+use super::client::ApiClient;
 use super::endpoint::Endpoint;
+use super::fetch_async;
+use crate::error::Result;
 
-pub fn fetch<T: Endpoint, U: Authentication>(
+// A successful response which has been read in full.
+pub struct Response {
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+pub fn send<T: Endpoint, U: Authentication, B: Serialize>(
+    client: &ApiClient,
+    method: Method,
     endpoint: &T,
     authentication: &U,
-) -> reqwest::Result<Response> {
-    let client = Client::new();
-    let url = endpoint.url();
-    println!("url: {}", url);
-    let mut request_builder = client.get(&url);
-    request_builder = authentication.apply(request_builder);
-    request_builder.send()
+    body: Option<&B>,
+) -> Result<Response> {
+    client.block_on(async {
+        let response = fetch_async::send(client, method, endpoint, authentication, body).await?;
+        let headers = response.headers().clone();
+        let body = response.bytes().await?.to_vec();
+        Ok(Response { headers, body })
+    })
 }
 
-pub fn get<T: Endpoint, U: Authentication>(
+pub fn fetch<T: Endpoint, U: Authentication>(
+    client: &ApiClient,
     endpoint: &T,
     authentication: &U,
-) -> reqwest::Result<Value> {
-    let response = fetch(endpoint, authentication)?;
-    response.json()
+) -> Result<Response> {
+    send::<T, U, ()>(client, Method::GET, endpoint, authentication, None)
 }
 
-pub fn get_list<T: Endpoint, U: Authentication>(
+pub fn parse<R: DeserializeOwned>(response: Response) -> Result<R> {
+    fetch_async::parse_body(&response.body)
+}
+
+pub fn get<R: DeserializeOwned, T: Endpoint, U: Authentication>(
+    client: &ApiClient,
     endpoint: &T,
     authentication: &U,
-) -> reqwest::Result<Vec<Value>> {
-    let mut output = Vec::new();
-    let value = get(endpoint, authentication)?;
-    if value.is_array() {
-        let list = value.as_array().unwrap();
-        output.extend(list.clone().into_iter());
-    } else {
-        output.push(value);
-    }
-    Ok(output)
+) -> Result<R> {
+    client.block_on(fetch_async::get(client, endpoint, authentication))
 }
 
-// pub fn post<T: Endpoint, U: Authentication>(
-//     endpoint: &T,
-//     authentication: &U,
-// ) -> reqwest::Result<Value> {
-//     let client = Client::new();
-//     let url = endpoint.url();
-//     println!("url: {}", url);
-//     let mut request_builder = client.post(&url);
-//     request_builder = authentication.apply(request_builder);
-//     let response = request_builder.send()?;
-//     response.json()
-// }
+pub fn get_list<R: DeserializeOwned, T: Endpoint, U: Authentication>(
+    client: &ApiClient,
+    endpoint: &T,
+    authentication: &U,
+) -> Result<Vec<R>> {
+    client.block_on(fetch_async::get_list(client, endpoint, authentication))
+}
 
-//document_id: u32
-//let url = format!("{}/opportunity_documents/{}.pdf", BASE_URL, document_id);
-// pub fn retrieve_binary<T: Endpoint, U: Authentication>(source: &Source, endpoint: &str) -> reqwest::Result<(Vec<u8>, String)> {
-//     let mut response = source.get(endpoint)?;
+pub fn post<R: DeserializeOwned, T: Endpoint, U: Authentication, B: Serialize>(
+    client: &ApiClient,
+    endpoint: &T,
+    authentication: &U,
+    body: &B,
+) -> Result<R> {
+    parse(send(
+        client,
+        Method::POST,
+        endpoint,
+        authentication,
+        Some(body),
+    )?)
+}
 
-//     // Retrieve filename;
-//     let mut filename = String::from("picking_list.pdf");
-//     if let Some(content_disposition) = response.headers().get(CONTENT_DISPOSITION) {
-//         let mut str = content_disposition.to_str().unwrap();
-//         if let Some(c) = str.find('"') {
-//             str = &str[(c + 1)..];
-//             if let Some(c) = str.find('"') {
-//                 str = &str[..c];
-//             }
-//             filename = str.to_string();
-//         }
-//     }
+pub fn put<R: DeserializeOwned, T: Endpoint, U: Authentication, B: Serialize>(
+    client: &ApiClient,
+    endpoint: &T,
+    authentication: &U,
+    body: &B,
+) -> Result<R> {
+    parse(send(
+        client,
+        Method::PUT,
+        endpoint,
+        authentication,
+        Some(body),
+    )?)
+}
 
-//     let mut buffer = Vec::new();
-//     response.copy_to(&mut buffer)?;
-//     Ok((buffer, filename))
-// }
+pub fn delete<T: Endpoint, U: Authentication>(
+    client: &ApiClient,
+    endpoint: &T,
+    authentication: &U,
+) -> Result<()> {
+    send::<T, U, ()>(client, Method::DELETE, endpoint, authentication, None)?;
+    Ok(())
+}
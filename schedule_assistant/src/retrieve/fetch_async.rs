@@ -0,0 +1,133 @@
+// The asynchronous requests that everything else is built on, independent
+// requests can be joined so that they are made concurrently.
+
+use reqwest::{Method, Response};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::authentication::Authentication;
+use super::cassette::Mode;
+use super::client::ApiClient;
+use super::endpoint::Endpoint;
+use super::retry;
+use crate::error::{Error, Result};
+
+// Some list endpoints return a bare object rather than an array when there is only
+// a single record.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    Many(Vec<T>),
+    One(T),
+}
+
+// Longest response body that we'll include in an error message.
+const MAX_ERROR_BODY: usize = 512;
+
+pub async fn send<T: Endpoint, U: Authentication, B: Serialize>(
+    client: &ApiClient,
+    method: Method,
+    endpoint: &T,
+    authentication: &U,
+    body: Option<&B>,
+) -> Result<Response> {
+    let url = endpoint.url();
+    let cassette = client.cassette();
+    if let Some(cassette) = cassette.filter(|cassette| cassette.mode() == Mode::Replay) {
+        println!("{} {} (replayed)", method, url);
+        return check_status(&url, cassette.play(&method, &url)?).await;
+    }
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        client.throttle(endpoint.service()).await;
+        println!("{} {}", method, url);
+        let mut request_builder = client.request(method.clone(), &url);
+        request_builder = authentication.apply(request_builder);
+        if let Some(body) = body {
+            request_builder = request_builder.json(body);
+        }
+
+        // Temporary failures are retried a few times before giving up.
+        let delay = match request_builder.send().await {
+            Ok(response) => {
+                let status = response.status();
+                if attempt >= retry::MAX_ATTEMPTS || !retry::should_retry_status(&method, status) {
+                    let response = match cassette {
+                        Some(cassette) => cassette.capture(&method, &url, body, response).await?,
+                        None => response,
+                    };
+                    return check_status(&url, response).await;
+                }
+                let delay = retry::retry_after(response.headers())
+                    .unwrap_or_else(|| retry::backoff(attempt));
+                println!("{} {} returned {}", method, url, status);
+                delay
+            }
+            Err(e) => {
+                if attempt >= retry::MAX_ATTEMPTS || !retry::should_retry_error(&method, &e) {
+                    return Err(e.into());
+                }
+                println!("{} {} failed, {}", method, url, e);
+                retry::backoff(attempt)
+            }
+        };
+        println!("Retrying in {:.1}s", delay.as_secs_f64());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Converts any non-success response into an Error so that we never attempt to
+// parse an error page as the record that we asked for.
+async fn check_status(url: &str, response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let url = url.to_string();
+    let mut body = response.text().await.unwrap_or_default();
+    if body.len() > MAX_ERROR_BODY {
+        let mut end = MAX_ERROR_BODY;
+        while !body.is_char_boundary(end) {
+            end -= 1;
+        }
+        body.truncate(end);
+    }
+    Err(Error::Status { url, status, body })
+}
+
+// Parses a response body, treating an empty body as null so that callers which
+// don't care about the response can ask for a Value or ().
+pub fn parse_body<R: DeserializeOwned>(bytes: &[u8]) -> Result<R> {
+    if bytes.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(serde_json::from_str("null")?);
+    }
+    Ok(serde_json::from_slice(bytes)?)
+}
+
+pub async fn parse<R: DeserializeOwned>(response: Response) -> Result<R> {
+    let bytes = response.bytes().await?;
+    parse_body(&bytes)
+}
+
+pub async fn get<R: DeserializeOwned, T: Endpoint, U: Authentication>(
+    client: &ApiClient,
+    endpoint: &T,
+    authentication: &U,
+) -> Result<R> {
+    let response = send::<T, U, ()>(client, Method::GET, endpoint, authentication, None).await?;
+    parse(response).await
+}
+
+pub async fn get_list<R: DeserializeOwned, T: Endpoint, U: Authentication>(
+    client: &ApiClient,
+    endpoint: &T,
+    authentication: &U,
+) -> Result<Vec<R>> {
+    match get(client, endpoint, authentication).await? {
+        OneOrMany::Many(list) => Ok(list),
+        OneOrMany::One(value) => Ok(vec![value]),
+    }
+}
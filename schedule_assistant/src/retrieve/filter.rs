@@ -0,0 +1,111 @@
+// Builds the OData style `$filter` expressions that ServiceM8 accepts on its
+// list endpoints, ie `active eq 1 and start_date gt '2021-03-22 00:00:00'`.
+//
+// ServiceM8 only supports the eq, ne, gt and lt operators joined with "and".
+
+use crate::models::format::servicem8_date;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterValue {
+    Number(i64),
+    Text(String),
+}
+
+impl FilterValue {
+    fn to_expression(&self) -> String {
+        match self {
+            FilterValue::Number(n) => n.to_string(),
+            // Quotes within a string literal are escaped by doubling them.
+            FilterValue::Text(s) => format!("'{}'", s.replace('\'', "''")),
+        }
+    }
+}
+
+impl From<i64> for FilterValue {
+    fn from(n: i64) -> Self {
+        FilterValue::Number(n)
+    }
+}
+
+impl From<&str> for FilterValue {
+    fn from(s: &str) -> Self {
+        FilterValue::Text(s.to_string())
+    }
+}
+
+impl From<String> for FilterValue {
+    fn from(s: String) -> Self {
+        FilterValue::Text(s)
+    }
+}
+
+impl From<DateTime<Utc>> for FilterValue {
+    fn from(date: DateTime<Utc>) -> Self {
+        FilterValue::Text(date.format(servicem8_date::FORMAT).to_string())
+    }
+}
+
+// Dates compare as midnight at the start of the day.
+impl From<NaiveDate> for FilterValue {
+    fn from(date: NaiveDate) -> Self {
+        Utc.from_utc_datetime(&date.and_time(Default::default()))
+            .into()
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    clauses: Vec<String>,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Filter::default()
+    }
+
+    fn clause(mut self, field: &str, operator: &str, value: FilterValue) -> Self {
+        self.clauses
+            .push(format!("{} {} {}", field, operator, value.to_expression()));
+        self
+    }
+
+    pub fn eq(self, field: &str, value: impl Into<FilterValue>) -> Self {
+        self.clause(field, "eq", value.into())
+    }
+
+    pub fn ne(self, field: &str, value: impl Into<FilterValue>) -> Self {
+        self.clause(field, "ne", value.into())
+    }
+
+    pub fn gt(self, field: &str, value: impl Into<FilterValue>) -> Self {
+        self.clause(field, "gt", value.into())
+    }
+
+    pub fn lt(self, field: &str, value: impl Into<FilterValue>) -> Self {
+        self.clause(field, "lt", value.into())
+    }
+
+    // Only records which haven't been deleted.
+    pub fn active(self) -> Self {
+        self.eq("active", 1)
+    }
+
+    // Only records changed after the date.
+    pub fn edited_after(self, date: DateTime<Utc>) -> Self {
+        self.gt("edit_date", date)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clauses.is_empty()
+    }
+
+    // The value of the $filter parameter, None when there is nothing to filter on.
+    pub fn expression(&self) -> Option<String> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.clauses.join(" and "))
+        }
+    }
+}
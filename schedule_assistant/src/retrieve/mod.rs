@@ -1,5 +1,13 @@
 pub mod authentication;
+pub mod cassette;
+pub mod client;
 pub mod endpoint;
 pub mod fetch;
+pub mod fetch_async;
+pub mod filter;
+pub mod query;
+pub mod rate_limit;
+pub mod retry;
+pub mod scrub;
 
-//@todo: should we definite anything in this actually file??
\ No newline at end of file
+//@todo: should we definite anything in this actually file??
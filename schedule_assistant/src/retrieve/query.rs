@@ -0,0 +1,104 @@
+// Builds the ransack style filters that Current RMS accepts on its list
+// endpoints, ie `q[state_eq]=3` or `q[starts_at_gteq]=2021-03-22`.
+
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+
+// Anything that can be compared against a date attribute.
+pub trait QueryDate {
+    fn to_query(&self) -> String;
+}
+
+impl QueryDate for NaiveDate {
+    fn to_query(&self) -> String {
+        self.format("%Y-%m-%d").to_string()
+    }
+}
+
+impl QueryDate for DateTime<Utc> {
+    fn to_query(&self) -> String {
+        self.to_rfc3339_opts(SecondsFormat::Millis, true)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    params: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Query::default()
+    }
+
+    // Adds a raw predicate, ie ("state", "eq", "3") becomes q[state_eq]=3.
+    pub fn predicate(mut self, attribute: &str, predicate: &str, value: impl ToString) -> Self {
+        self.params
+            .push((format!("q[{}_{}]", attribute, predicate), value.to_string()));
+        self
+    }
+
+    // Matches any of the values, ie q[state_in][]=2&q[state_in][]=3.
+    pub fn any_of<T: ToString>(mut self, attribute: &str, values: &[T]) -> Self {
+        let key = format!("q[{}_in][]", attribute);
+        for value in values {
+            self.params.push((key.clone(), value.to_string()));
+        }
+        self
+    }
+
+    pub fn state(self, state: u32) -> Self {
+        self.predicate("state", "eq", state)
+    }
+
+    pub fn states(self, states: &[u32]) -> Self {
+        self.any_of("state", states)
+    }
+
+    pub fn member(self, member_id: u64) -> Self {
+        self.predicate("member_id", "eq", member_id)
+    }
+
+    pub fn store(self, store_id: u64) -> Self {
+        self.predicate("store_id", "eq", store_id)
+    }
+
+    pub fn tags(self, tags: &[&str]) -> Self {
+        self.any_of("tags_name", tags)
+    }
+
+    // Bounds on when the opportunity starts, the lower bound is inclusive and the
+    // upper bound exclusive.
+    pub fn starts_from<D: QueryDate>(self, date: &D) -> Self {
+        self.predicate("starts_at", "gteq", date.to_query())
+    }
+
+    pub fn starts_before<D: QueryDate>(self, date: &D) -> Self {
+        self.predicate("starts_at", "lt", date.to_query())
+    }
+
+    // Bounds on when the opportunity ends, as above.
+    pub fn ends_from<D: QueryDate>(self, date: &D) -> Self {
+        self.predicate("ends_at", "gteq", date.to_query())
+    }
+
+    pub fn ends_before<D: QueryDate>(self, date: &D) -> Self {
+        self.predicate("ends_at", "lt", date.to_query())
+    }
+
+    // Only opportunities changed at or after the date.
+    pub fn updated_from<D: QueryDate>(self, date: &D) -> Self {
+        self.predicate("updated_at", "gteq", date.to_query())
+    }
+
+    // Sorts the results, ie ("starts_at", true) for the earliest first.
+    pub fn sort(mut self, attribute: &str, ascending: bool) -> Self {
+        let direction = if ascending { "asc" } else { "desc" };
+        self.params
+            .push((String::from("q[s]"), format!("{} {}", attribute, direction)));
+        self
+    }
+
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+}
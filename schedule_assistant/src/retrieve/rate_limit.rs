@@ -0,0 +1,39 @@
+// Spaces out the requests made to a service so that we stay under its rate
+// limit instead of relying on it to turn us away.
+//
+// Current RMS allows 60 requests a minute and ServiceM8 180 a minute.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn per_minute(requests: u32) -> RateLimiter {
+        RateLimiter {
+            interval: Duration::from_secs(60) / requests.max(1),
+            next: Mutex::new(None),
+        }
+    }
+
+    // Claims the next free slot and waits for it to arrive.
+    pub async fn wait(&self) {
+        let delay = {
+            let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let send_at = match *next {
+                Some(next) if next > now => next,
+                _ => now,
+            };
+            *next = Some(send_at + self.interval);
+            send_at - now
+        };
+
+        if delay > Duration::ZERO {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
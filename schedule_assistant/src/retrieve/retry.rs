@@ -0,0 +1,79 @@
+// When and how long to wait before retrying a request that failed for reasons
+// that are likely to be temporary, ie a 429 or a 503 from the service.
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+pub const MAX_ATTEMPTS: u32 = 5;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+// Longest that we'll wait when the service asks us to come back later.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+// Requests which can be repeated without changing the outcome.  A POST that
+// failed with a 5xx may still have been applied so it isn't retried.
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
+}
+
+pub fn should_retry_status(method: &Method, status: StatusCode) -> bool {
+    match status {
+        // Rate limited, the request wasn't processed.
+        StatusCode::TOO_MANY_REQUESTS => true,
+        StatusCode::INTERNAL_SERVER_ERROR
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT => is_idempotent(method),
+        _ => false,
+    }
+}
+
+pub fn should_retry_error(method: &Method, error: &reqwest::Error) -> bool {
+    is_idempotent(method) && (error.is_timeout() || error.is_connect())
+}
+
+// The delay requested by the Retry-After header, which is either a number of
+// seconds or an HTTP date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&Utc) - Utc::now())
+                .to_std()
+                .unwrap_or_default()
+        }
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+// Exponential backoff with full jitter, attempt starts at 1.
+pub fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_DELAY
+        .checked_mul(1 << attempt.saturating_sub(1).min(16))
+        .unwrap_or(MAX_DELAY)
+        .min(MAX_DELAY);
+    let fraction = random() as f64 / u64::MAX as f64;
+    ceiling.mul_f64(fraction)
+}
+
+// Good enough randomness for jitter without pulling in another crate.
+fn random() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
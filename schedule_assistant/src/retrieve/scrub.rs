@@ -0,0 +1,153 @@
+// Removes personal data from responses before they are written to a cassette.
+//
+// The values of personal fields are replaced word by word with pseudonyms, the
+// same word always becoming the same pseudonym so that records which refer to
+// the same person in both services still match.  Case, digits and punctuation
+// are kept so that the shape of the data survives.
+//
+// The pseudonyms only depend on the word and the salt, so recording the same
+// data again gives the same cassette.  Set a secret salt when recording the
+// real services so that the pseudonyms can't be reversed by trying a list of
+// likely names.
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+// Fields whose values, and anything nested within them, are personal.
+static PERSONAL_FIELDS: &[&str] = &[
+    "name",
+    "first",
+    "last",
+    "email",
+    "address",
+    "address_street",
+    "address_city",
+    "address_postcode",
+    "billing_address",
+    "job_address",
+    "street",
+    "city",
+    "county",
+    "postcode",
+    "phone",
+    "mobile",
+    "number",
+    "subject",
+    "description",
+    "job_description",
+];
+
+static CONSONANTS: &[u8] = b"bcdfghjklmnprstvwz";
+static VOWELS: &[u8] = b"aeiou";
+
+pub struct Scrubber {
+    salt: String,
+    pseudonyms: HashMap<String, String>,
+}
+
+impl Scrubber {
+    pub fn new(salt: &str) -> Scrubber {
+        Scrubber {
+            salt: salt.to_string(),
+            pseudonyms: HashMap::new(),
+        }
+    }
+
+    pub fn scrub(&mut self, value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                for (key, value) in fields.iter_mut() {
+                    if PERSONAL_FIELDS.contains(&key.as_str()) {
+                        self.scrub_all(value);
+                    } else {
+                        self.scrub(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.scrub(value)),
+            _ => (),
+        }
+    }
+
+    fn scrub_all(&mut self, value: &mut Value) {
+        match value {
+            Value::String(text) => *text = self.scrub_text(text),
+            Value::Object(fields) => fields.values_mut().for_each(|value| self.scrub_all(value)),
+            Value::Array(values) => values.iter_mut().for_each(|value| self.scrub_all(value)),
+            _ => (),
+        }
+    }
+
+    fn scrub_text(&mut self, text: &str) -> String {
+        let mut scrubbed = String::with_capacity(text.len());
+        let mut word = String::new();
+        for c in text.chars().chain(std::iter::once(' ')) {
+            if c.is_alphanumeric() {
+                word.push(c);
+                continue;
+            }
+            if !word.is_empty() {
+                scrubbed.push_str(&self.pseudonym(&word));
+                word.clear();
+            }
+            scrubbed.push(c);
+        }
+        scrubbed.pop();
+        scrubbed
+    }
+
+    fn pseudonym(&mut self, word: &str) -> String {
+        let key = word.to_lowercase();
+        let salt = &self.salt;
+        let pseudonym = self
+            .pseudonyms
+            .entry(key.clone())
+            .or_insert_with(|| generate(salt, &key));
+
+        // Match the case of the original, ie Smith -> Vobat and SMITH -> VOBAT.
+        word.chars()
+            .zip(pseudonym.chars())
+            .map(|(original, replacement)| {
+                if original.is_uppercase() {
+                    replacement.to_ascii_uppercase()
+                } else {
+                    replacement
+                }
+            })
+            .collect()
+    }
+}
+
+// 64 bit FNV-1a, unlike the standard library's hasher its output is fixed so
+// the pseudonyms don't change between versions of Rust.
+fn fnv1a(parts: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        // Separate the parts so that ("ab", "c") and ("a", "bc") differ.
+        for byte in part.bytes().chain(std::iter::once(0xff)) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+// A pronounceable replacement of the same length, digits stay digits.
+fn generate(salt: &str, word: &str) -> String {
+    let mut bits = fnv1a(&[salt, word]);
+
+    word.chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let n = bits as usize;
+            bits = bits.rotate_right(7) ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+            if c.is_ascii_digit() {
+                (b'0' + (n % 10) as u8) as char
+            } else if i % 2 == 0 {
+                CONSONANTS[n % CONSONANTS.len()] as char
+            } else {
+                VOWELS[n % VOWELS.len()] as char
+            }
+        })
+        .collect()
+}
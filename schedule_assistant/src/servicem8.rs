@@ -1,43 +1,247 @@
 use crate::authentication::AuthenticationCache;
 use crate::endpoints::servicem8;
-use crate::retrieve::fetch;
+use crate::error::{Error, Result};
+use crate::models::{Company, Job, JobActivity, JobContact};
+use crate::retrieve::authentication::Authentication;
+use crate::retrieve::client::ApiClient;
+use crate::retrieve::endpoint::BasicEndpoint;
+use crate::retrieve::{fetch, fetch_async};
+use chrono::{Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::Value;
 
+static RECORD_UUID_HEADER: &str = "x-record-uuid";
+
+pub use crate::retrieve::filter::{Filter, FilterValue};
+
 // [Retrieval]
-pub fn clients(auth_cache: &AuthenticationCache) -> reqwest::Result<Vec<Value>> {
-    let endpoint = servicem8::clients();
+// Each of these downloads the whole table, the *_matching variants have
+// ServiceM8 filter the records first.
+pub fn clients(auth_cache: &AuthenticationCache) -> Result<Vec<Company>> {
+    clients_matching(auth_cache, &Filter::new())
+}
+
+pub fn clients_matching(auth_cache: &AuthenticationCache, filter: &Filter) -> Result<Vec<Company>> {
+    let endpoint = servicem8::clients(&auth_cache.urls().servicem8_api, filter);
     let authentication = auth_cache.servicem8();
-    let list = fetch::get_list(&endpoint, authentication)?;
+    let list = fetch::get_list(auth_cache.client(), &endpoint, authentication)?;
     println!("Found {} clients", list.len());
     Ok(list)
 }
 
-pub fn job_activities(auth_cache: &AuthenticationCache) -> reqwest::Result<Vec<Value>> {
-    let endpoint = servicem8::activities();
+pub fn job_activities(auth_cache: &AuthenticationCache) -> Result<Vec<JobActivity>> {
+    job_activities_matching(auth_cache, &Filter::new())
+}
+
+pub fn job_activities_matching(
+    auth_cache: &AuthenticationCache,
+    filter: &Filter,
+) -> Result<Vec<JobActivity>> {
+    let endpoint = servicem8::activities(&auth_cache.urls().servicem8_api, filter);
     let authentication = auth_cache.servicem8();
-    let list = fetch::get_list(&endpoint, authentication)?;
+    let list = fetch::get_list(auth_cache.client(), &endpoint, authentication)?;
     println!("Found {} activities", list.len());
     Ok(list)
 }
 
-pub fn jobs(auth_cache: &AuthenticationCache) -> reqwest::Result<Vec<Value>> {
-    let endpoint = servicem8::jobs();
+pub fn jobs(auth_cache: &AuthenticationCache) -> Result<Vec<Job>> {
+    jobs_matching(auth_cache, &Filter::new())
+}
+
+pub fn jobs_matching(auth_cache: &AuthenticationCache, filter: &Filter) -> Result<Vec<Job>> {
+    let endpoint = servicem8::jobs(&auth_cache.urls().servicem8_api, filter);
     let authentication = auth_cache.servicem8();
-    let list = fetch::get_list(&endpoint, authentication)?;
+    let list = fetch::get_list(auth_cache.client(), &endpoint, authentication)?;
     println!("Found {} jobs", list.len());
     Ok(list)
 }
 
-pub fn job_contacts(auth_cache: &AuthenticationCache) -> reqwest::Result<Vec<Value>> {
-    let endpoint = servicem8::contacts();
+pub fn job_contacts(auth_cache: &AuthenticationCache) -> Result<Vec<JobContact>> {
+    job_contacts_matching(auth_cache, &Filter::new())
+}
+
+pub fn job_contacts_matching(
+    auth_cache: &AuthenticationCache,
+    filter: &Filter,
+) -> Result<Vec<JobContact>> {
+    let endpoint = servicem8::contacts(&auth_cache.urls().servicem8_api, filter);
     let authentication = auth_cache.servicem8();
-    let list = fetch::get_list(&endpoint, authentication)?;
+    let list = fetch::get_list(auth_cache.client(), &endpoint, authentication)?;
     println!("Found {} contacts", list.len());
     Ok(list)
 }
 
-pub fn activity_is_active(activity: &Value) -> bool {
-    let value = activity["active"].as_u64()
-         .expect("Activity doesn't have an 'active' value");
-    value == 1
+// The tables that the tools work with, downloaded together.
+#[derive(Debug, Default)]
+pub struct Tables {
+    pub clients: Vec<Company>,
+    pub jobs: Vec<Job>,
+    pub activities: Vec<JobActivity>,
+    pub contacts: Vec<JobContact>,
+}
+
+// The filter to apply to each table, tables which are left as None aren't
+// downloaded at all.
+#[derive(Clone, Debug, Default)]
+pub struct TableFilters {
+    pub clients: Option<Filter>,
+    pub jobs: Option<Filter>,
+    pub activities: Option<Filter>,
+    pub contacts: Option<Filter>,
+}
+
+impl TableFilters {
+    // Every record in every table.
+    pub fn all() -> Self {
+        TableFilters {
+            clients: Some(Filter::new()),
+            jobs: Some(Filter::new()),
+            activities: Some(Filter::new()),
+            contacts: Some(Filter::new()),
+        }
+    }
+}
+
+pub fn tables(auth_cache: &AuthenticationCache) -> Result<Tables> {
+    tables_matching(auth_cache, &TableFilters::all())
+}
+
+// Requests each of the tables at the same time rather than one after another.
+pub fn tables_matching(auth_cache: &AuthenticationCache, filters: &TableFilters) -> Result<Tables> {
+    let client = auth_cache.client();
+    let authentication = auth_cache.servicem8();
+    let base = &auth_cache.urls().servicem8_api;
+    let endpoint = |filter: &Option<Filter>, build: fn(&str, &Filter) -> BasicEndpoint| {
+        filter.as_ref().map(|filter| build(base, filter))
+    };
+    let clients = endpoint(&filters.clients, servicem8::clients);
+    let jobs = endpoint(&filters.jobs, servicem8::jobs);
+    let activities = endpoint(&filters.activities, servicem8::activities);
+    let contacts = endpoint(&filters.contacts, servicem8::contacts);
+
+    let (clients, jobs, activities, contacts) = client.block_on(async {
+        futures::try_join!(
+            table(client, authentication, clients),
+            table(client, authentication, jobs),
+            table(client, authentication, activities),
+            table(client, authentication, contacts),
+        )
+    })?;
+
+    println!(
+        "Found {} clients, {} jobs, {} activities and {} contacts",
+        clients.len(),
+        jobs.len(),
+        activities.len(),
+        contacts.len()
+    );
+    Ok(Tables {
+        clients,
+        jobs,
+        activities,
+        contacts,
+    })
+}
+
+// Tables without an endpoint are skipped.
+async fn table<R: DeserializeOwned, U: Authentication>(
+    client: &ApiClient,
+    authentication: &U,
+    endpoint: Option<BasicEndpoint>,
+) -> Result<Vec<R>> {
+    match endpoint {
+        Some(endpoint) => fetch_async::get_list(client, &endpoint, authentication).await,
+        None => Ok(Vec::new()),
+    }
+}
+
+// The activities scheduled to start within [start, end).  ServiceM8 has no
+// inclusive comparison so the lower bound is moved back a second.
+pub fn activities_between(start: NaiveDate, end: NaiveDate) -> Filter {
+    let from = Utc.from_utc_datetime(&start.and_time(NaiveTime::default())) - Duration::seconds(1);
+    Filter::new()
+        .active()
+        .gt("start_date", from)
+        .lt("start_date", end)
+}
+
+// Everything attached to a particular job, ie its activities or contacts.
+pub fn for_job(job_uuid: &str) -> Filter {
+    Filter::new().active().eq("job_uuid", job_uuid)
+}
+
+pub fn activity_is_active(activity: &JobActivity) -> bool {
+    activity.active
+}
+
+// The ServiceM8 tables that we write to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Table {
+    Company,
+    Job,
+    JobActivity,
+    JobContact,
+}
+
+impl Table {
+    pub fn name(self) -> &'static str {
+        match self {
+            Table::Company => "company",
+            Table::Job => "job",
+            Table::JobActivity => "jobactivity",
+            Table::JobContact => "jobcontact",
+        }
+    }
+}
+
+// [Modification]
+// Creates a record, returning the uuid that ServiceM8 assigned to it.  If the
+// record includes a uuid then ServiceM8 will use that instead.
+pub fn create_record<B: Serialize>(
+    auth_cache: &AuthenticationCache,
+    table: Table,
+    record: &B,
+) -> Result<String> {
+    let endpoint = servicem8::table(&auth_cache.urls().servicem8_api, table.name());
+    let authentication = auth_cache.servicem8();
+    let response = fetch::send(
+        auth_cache.client(),
+        Method::POST,
+        &endpoint,
+        authentication,
+        Some(record),
+    )?;
+    response
+        .headers
+        .get(RECORD_UUID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+        .ok_or_else(|| {
+            Error::DataIntegrity(format!(
+                "ServiceM8 didn't return the uuid of the new {} record",
+                table.name()
+            ))
+        })
+}
+
+// Updates the given fields of a record, any fields not supplied are left as is.
+pub fn update_record<B: Serialize>(
+    auth_cache: &AuthenticationCache,
+    table: Table,
+    uuid: &str,
+    fields: &B,
+) -> Result<()> {
+    let endpoint = servicem8::record(&auth_cache.urls().servicem8_api, table.name(), uuid);
+    let authentication = auth_cache.servicem8();
+    let _: Value = fetch::post(auth_cache.client(), &endpoint, authentication, fields)?;
+    Ok(())
+}
+
+// ServiceM8 doesn't remove records, deleting one marks it as inactive.
+pub fn delete_record(auth_cache: &AuthenticationCache, table: Table, uuid: &str) -> Result<()> {
+    let endpoint = servicem8::record(&auth_cache.urls().servicem8_api, table.name(), uuid);
+    let authentication = auth_cache.servicem8();
+    fetch::delete(auth_cache.client(), &endpoint, authentication)
 }
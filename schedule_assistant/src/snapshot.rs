@@ -0,0 +1,554 @@
+// Timestamped copies of the records from both services kept in a local SQLite
+// database, so that a run can report what has changed since the one before it
+// rather than only how things stand now.
+//
+// The database is .schedule_snapshots.sqlite in the current directory unless
+// SNAPSHOT_DB is set.  Every record is stored as the JSON we would have
+// received for it, keyed by its kind and id.  Rather than copying every record
+// into each snapshot, a version of a record is stored when it changes along
+// with the snapshots that it was current for, so a snapshot only adds the
+// records which changed since the one before.  The same database holds the
+// synced copy of the records that snapshots are taken from, see the sync
+// module.
+
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::path::Path;
+
+use crate::authentication::AuthenticationCache;
+use crate::error::{Error, Result};
+use crate::models::{Address, Job, JobActivity, Opportunity};
+use crate::servicem8::Tables;
+use crate::sync::{self, TableSync};
+
+static DEFAULT_SNAPSHOT_DB: &str = ".schedule_snapshots.sqlite";
+
+static SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS snapshots (
+        id INTEGER PRIMARY KEY,
+        taken_at TEXT NOT NULL
+    );
+    -- A version is in every snapshot from first_snapshot up to, but not
+    -- including, last_snapshot, which is NULL while it is still current.
+    CREATE TABLE IF NOT EXISTS versions (
+        kind TEXT NOT NULL,
+        key TEXT NOT NULL,
+        record TEXT NOT NULL,
+        first_snapshot INTEGER NOT NULL REFERENCES snapshots (id),
+        last_snapshot INTEGER REFERENCES snapshots (id),
+        PRIMARY KEY (kind, key, first_snapshot)
+    );
+    CREATE INDEX IF NOT EXISTS current_versions ON versions (kind, key) WHERE last_snapshot IS NULL;
+    CREATE TABLE IF NOT EXISTS synced_records (
+        kind TEXT NOT NULL,
+        key TEXT NOT NULL,
+        record TEXT NOT NULL,
+        active INTEGER NOT NULL,
+        PRIMARY KEY (kind, key)
+    );
+    CREATE TABLE IF NOT EXISTS syncs (
+        kind TEXT PRIMARY KEY,
+        watermark TEXT,
+        full_sync_at TEXT NOT NULL,
+        synced_at TEXT NOT NULL
+    );
+";
+
+// The kind that each type of record is stored under.
+pub(crate) static OPPORTUNITY: &str = "opportunity";
+pub(crate) static CLIENT: &str = "client";
+pub(crate) static JOB: &str = "job";
+pub(crate) static ACTIVITY: &str = "activity";
+pub(crate) static CONTACT: &str = "contact";
+
+#[derive(Debug)]
+pub struct Snapshot {
+    pub id: i64,
+    pub taken_at: DateTime<Utc>,
+    pub opportunities: Vec<Opportunity>,
+    pub tables: Tables,
+}
+
+pub struct SnapshotStore {
+    pub(crate) connection: Connection,
+}
+
+impl SnapshotStore {
+    // Opens the database at path, creating it if it doesn't exist.
+    pub fn open(path: &Path) -> Result<SnapshotStore> {
+        SnapshotStore::with_connection(Connection::open(path)?)
+    }
+
+    pub fn from_env() -> Result<SnapshotStore> {
+        let path = env::var("SNAPSHOT_DB").unwrap_or_else(|_| String::from(DEFAULT_SNAPSHOT_DB));
+        SnapshotStore::open(Path::new(&path))
+    }
+
+    // A store which is thrown away when it is dropped.
+    pub fn in_memory() -> Result<SnapshotStore> {
+        SnapshotStore::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(connection: Connection) -> Result<SnapshotStore> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(SnapshotStore { connection })
+    }
+
+    // Stores the records as a new snapshot taken now, adding a version of each
+    // record which has changed and ending those of the records which have gone.
+    // The snapshot is written in a single transaction so that an interrupted
+    // run leaves no partial snapshot behind.
+    pub fn save(&mut self, opportunities: Vec<Opportunity>, tables: Tables) -> Result<Snapshot> {
+        let mut records = Vec::new();
+        for opportunity in &opportunities {
+            records.push((
+                OPPORTUNITY,
+                opportunity.id.to_string(),
+                serde_json::to_string(opportunity)?,
+            ));
+        }
+        for client in &tables.clients {
+            records.push((CLIENT, client.uuid.clone(), serde_json::to_string(client)?));
+        }
+        for job in &tables.jobs {
+            records.push((JOB, job.uuid.clone(), serde_json::to_string(job)?));
+        }
+        for activity in &tables.activities {
+            records.push((
+                ACTIVITY,
+                activity.uuid.clone(),
+                serde_json::to_string(activity)?,
+            ));
+        }
+        for contact in &tables.contacts {
+            records.push((
+                CONTACT,
+                contact.uuid.clone(),
+                serde_json::to_string(contact)?,
+            ));
+        }
+
+        let taken_at = Utc::now();
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO snapshots (taken_at) VALUES (?1)",
+            params![taken_at],
+        )?;
+        let id = transaction.last_insert_rowid();
+        {
+            let mut current: HashMap<(String, String), String> = {
+                let mut select = transaction.prepare(
+                    "SELECT kind, key, record FROM versions WHERE last_snapshot IS NULL",
+                )?;
+                let rows =
+                    select.query_map([], |row| Ok(((row.get(0)?, row.get(1)?), row.get(2)?)))?;
+                rows.collect::<rusqlite::Result<_>>()?
+            };
+            let mut end = transaction.prepare(
+                "UPDATE versions SET last_snapshot = ?1
+                 WHERE kind = ?2 AND key = ?3 AND last_snapshot IS NULL",
+            )?;
+            let mut insert = transaction.prepare(
+                "INSERT INTO versions (kind, key, record, first_snapshot) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (kind, key, record) in records {
+                match current.remove(&(kind.to_string(), key.clone())) {
+                    Some(previous) if previous == record => continue,
+                    Some(_) => {
+                        end.execute(params![id, kind, key])?;
+                    }
+                    None => (),
+                }
+                insert.execute(params![kind, key, record, id])?;
+            }
+            // Whatever is left wasn't in this snapshot.
+            for (kind, key) in current.into_keys() {
+                end.execute(params![id, kind, key])?;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(Snapshot {
+            id,
+            taken_at,
+            opportunities,
+            tables,
+        })
+    }
+
+    // The id and time of every snapshot, oldest first.
+    pub fn list(&self) -> Result<Vec<(i64, DateTime<Utc>)>> {
+        let mut select = self
+            .connection
+            .prepare("SELECT id, taken_at FROM snapshots ORDER BY id")?;
+        let rows = select.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    pub fn load(&self, id: i64) -> Result<Option<Snapshot>> {
+        let taken_at: Option<DateTime<Utc>> = self
+            .connection
+            .query_row(
+                "SELECT taken_at FROM snapshots WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let taken_at = match taken_at {
+            Some(taken_at) => taken_at,
+            None => return Ok(None),
+        };
+
+        let mut opportunities: Vec<Opportunity> = self.records(id, OPPORTUNITY)?;
+        opportunities.sort_by_key(|opportunity| opportunity.id);
+        Ok(Some(Snapshot {
+            id,
+            taken_at,
+            opportunities,
+            tables: Tables {
+                clients: self.records(id, CLIENT)?,
+                jobs: self.records(id, JOB)?,
+                activities: self.records(id, ACTIVITY)?,
+                contacts: self.records(id, CONTACT)?,
+            },
+        }))
+    }
+
+    pub fn latest(&self) -> Result<Option<Snapshot>> {
+        match self.list()?.last() {
+            Some(&(id, _)) => self.load(id),
+            None => Ok(None),
+        }
+    }
+
+    // The records of one kind as they were in the snapshot, in order of key.
+    fn records<T: DeserializeOwned>(&self, id: i64, kind: &str) -> Result<Vec<T>> {
+        let mut select = self.connection.prepare(
+            "SELECT record FROM versions
+             WHERE kind = ?2 AND first_snapshot <= ?1
+               AND (last_snapshot IS NULL OR last_snapshot > ?1)
+             ORDER BY key",
+        )?;
+        let rows = select.query_map(params![id, kind], |row| row.get::<_, String>(0))?;
+        let mut records = Vec::new();
+        for row in rows {
+            records.push(serde_json::from_str(&row?)?);
+        }
+        Ok(records)
+    }
+}
+
+// Brings the synced records up to date and saves them as a new snapshot.
+pub fn take(
+    auth_cache: &AuthenticationCache,
+    store: &mut SnapshotStore,
+    mode: sync::Mode,
+) -> Result<(Vec<TableSync>, Snapshot)> {
+    let synced = sync::sync(auth_cache, store, mode)?;
+    let (opportunities, tables) = store.synced()?;
+    Ok((synced, store.save(opportunities, tables)?))
+}
+
+type Period = (DateTime<Utc>, DateTime<Utc>);
+
+fn format_period((start, end): &Period) -> String {
+    format!(
+        "{} - {}",
+        start.format("%Y-%m-%d %H:%M"),
+        end.format("%Y-%m-%d %H:%M")
+    )
+}
+
+// The record whose address changed.  Jobs are named by their number in
+// ServiceM8, or their uuid if they haven't one, and clients by their name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Record {
+    Opportunity(u64),
+    Job(String),
+    Client(String),
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Record::Opportunity(id) => write!(f, "opportunity {}", id),
+            Record::Job(job) => write!(f, "job {}", job),
+            Record::Client(name) => write!(f, "client {}", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    // An order which wasn't confirmed, or didn't exist, in the earlier snapshot.
+    NewBooking {
+        opportunity: u64,
+        subject: String,
+        period: Period,
+    },
+    // An order which was confirmed but no longer is, or has been deleted.
+    Cancelled {
+        opportunity: u64,
+        subject: String,
+    },
+    DatesChanged {
+        opportunity: u64,
+        from: Period,
+        to: Period,
+    },
+    // An activity which has been moved, added (from is None) or removed (to is
+    // None).
+    ActivityMoved {
+        job: String,
+        activity: String,
+        from: Option<Period>,
+        to: Option<Period>,
+    },
+    AddressChanged {
+        record: Record,
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::NewBooking {
+                opportunity,
+                subject,
+                period,
+            } => write!(
+                f,
+                "opportunity {} ({}): booked for {}",
+                opportunity,
+                subject,
+                format_period(period)
+            ),
+            Change::Cancelled {
+                opportunity,
+                subject,
+            } => write!(f, "opportunity {} ({}): cancelled", opportunity, subject),
+            Change::DatesChanged {
+                opportunity,
+                from,
+                to,
+            } => write!(
+                f,
+                "opportunity {}: moved from {} to {}",
+                opportunity,
+                format_period(from),
+                format_period(to)
+            ),
+            Change::ActivityMoved { job, from, to, .. } => match (from, to) {
+                (Some(from), Some(to)) => write!(
+                    f,
+                    "job {}: activity moved from {} to {}",
+                    job,
+                    format_period(from),
+                    format_period(to)
+                ),
+                (None, Some(to)) => {
+                    write!(f, "job {}: activity added for {}", job, format_period(to))
+                }
+                (Some(from), None) => {
+                    write!(
+                        f,
+                        "job {}: activity for {} removed",
+                        job,
+                        format_period(from)
+                    )
+                }
+                (None, None) => write!(f, "job {}: activity changed", job),
+            },
+            Change::AddressChanged { record, from, to } => {
+                write!(f, "{}: address changed from '{}' to '{}'", record, from, to)
+            }
+        }
+    }
+}
+
+fn opportunity_period(opportunity: &Opportunity) -> Period {
+    (opportunity.starts_at, opportunity.ends_at)
+}
+
+fn opportunity_address(opportunity: &Opportunity) -> String {
+    opportunity
+        .address()
+        .map(Address::one_line)
+        .unwrap_or_default()
+}
+
+fn job_label(job: &Job) -> String {
+    if job.generated_job_id.is_empty() {
+        job.uuid.clone()
+    } else {
+        job.generated_job_id.clone()
+    }
+}
+
+fn activity_period(activity: &JobActivity) -> Option<Period> {
+    if activity.active {
+        activity.period()
+    } else {
+        None
+    }
+}
+
+// What changed between the two snapshots, opportunities first and then the
+// ServiceM8 records.  Only confirmed orders are reported on, and only the dates
+// and addresses of the records are compared.
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+
+    let earlier: HashMap<u64, &Opportunity> = before
+        .opportunities
+        .iter()
+        .map(|opportunity| (opportunity.id, opportunity))
+        .collect();
+    let later: HashMap<u64, &Opportunity> = after
+        .opportunities
+        .iter()
+        .map(|opportunity| (opportunity.id, opportunity))
+        .collect();
+
+    for opportunity in after.opportunities.iter().filter(|op| op.is_confirmed()) {
+        let previous = match earlier.get(&opportunity.id) {
+            Some(previous) if previous.is_confirmed() => previous,
+            _ => {
+                changes.push(Change::NewBooking {
+                    opportunity: opportunity.id,
+                    subject: opportunity.subject.clone(),
+                    period: opportunity_period(opportunity),
+                });
+                continue;
+            }
+        };
+        if opportunity_period(previous) != opportunity_period(opportunity) {
+            changes.push(Change::DatesChanged {
+                opportunity: opportunity.id,
+                from: opportunity_period(previous),
+                to: opportunity_period(opportunity),
+            });
+        }
+        let (from, to) = (
+            opportunity_address(previous),
+            opportunity_address(opportunity),
+        );
+        if from != to {
+            changes.push(Change::AddressChanged {
+                record: Record::Opportunity(opportunity.id),
+                from,
+                to,
+            });
+        }
+    }
+    for previous in before.opportunities.iter().filter(|op| op.is_confirmed()) {
+        let still_confirmed = later
+            .get(&previous.id)
+            .is_some_and(|opportunity| opportunity.is_confirmed());
+        if !still_confirmed {
+            changes.push(Change::Cancelled {
+                opportunity: previous.id,
+                subject: previous.subject.clone(),
+            });
+        }
+    }
+
+    let earlier_jobs: HashMap<&str, &Job> = before
+        .tables
+        .jobs
+        .iter()
+        .map(|job| (job.uuid.as_str(), job))
+        .collect();
+    for job in &after.tables.jobs {
+        if let Some(previous) = earlier_jobs.get(job.uuid.as_str()) {
+            if previous.job_address.trim() != job.job_address.trim() {
+                changes.push(Change::AddressChanged {
+                    record: Record::Job(job_label(job)),
+                    from: previous.job_address.trim().to_string(),
+                    to: job.job_address.trim().to_string(),
+                });
+            }
+        }
+    }
+
+    let earlier_activities: HashMap<&str, &JobActivity> = before
+        .tables
+        .activities
+        .iter()
+        .map(|activity| (activity.uuid.as_str(), activity))
+        .collect();
+    let later_activities: HashMap<&str, &JobActivity> = after
+        .tables
+        .activities
+        .iter()
+        .map(|activity| (activity.uuid.as_str(), activity))
+        .collect();
+    let jobs: HashMap<&str, &Job> = before
+        .tables
+        .jobs
+        .iter()
+        .chain(&after.tables.jobs)
+        .map(|job| (job.uuid.as_str(), job))
+        .collect();
+    let removed = before
+        .tables
+        .activities
+        .iter()
+        .filter(|activity| !later_activities.contains_key(activity.uuid.as_str()));
+    for activity in after.tables.activities.iter().chain(removed) {
+        let from = earlier_activities
+            .get(activity.uuid.as_str())
+            .and_then(|previous| activity_period(previous));
+        let to = later_activities
+            .get(activity.uuid.as_str())
+            .and_then(|current| activity_period(current));
+        if from != to {
+            let job = jobs
+                .get(activity.job_uuid.as_str())
+                .map(|job| job_label(job))
+                .unwrap_or_else(|| activity.job_uuid.clone());
+            changes.push(Change::ActivityMoved {
+                job,
+                activity: activity.uuid.clone(),
+                from,
+                to,
+            });
+        }
+    }
+
+    let earlier_clients: HashMap<&str, _> = before
+        .tables
+        .clients
+        .iter()
+        .map(|client| (client.uuid.as_str(), client))
+        .collect();
+    for client in &after.tables.clients {
+        if let Some(previous) = earlier_clients.get(client.uuid.as_str()) {
+            if previous.address.trim() != client.address.trim() {
+                changes.push(Change::AddressChanged {
+                    record: Record::Client(client.name.clone()),
+                    from: previous.address.trim().to_string(),
+                    to: client.address.trim().to_string(),
+                });
+            }
+        }
+    }
+
+    changes
+}
+
+// Compares the two snapshots with the given ids.
+pub fn diff_between(store: &SnapshotStore, before: i64, after: i64) -> Result<Vec<Change>> {
+    let load = |id| {
+        store
+            .load(id)?
+            .ok_or_else(|| Error::Configuration(format!("there is no snapshot {}", id)))
+    };
+    Ok(diff(&load(before)?, &load(after)?))
+}
@@ -0,0 +1,330 @@
+// Keeps a local copy of every record up to date by only downloading those that
+// have changed since the last sync, going by edit_date in ServiceM8 and
+// updated_at in Current RMS.
+//
+// Neither service lists what has been deleted, so every SYNC_FULL_DAYS (7 by
+// default) everything is downloaded again and any record which has gone is
+// removed from the copy.  ServiceM8 only ever marks records as inactive, which
+// an incremental sync picks up as it would any other change.
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+
+use crate::authentication::AuthenticationCache;
+use crate::error::{Error, Result};
+use crate::models::{Company, Job, JobActivity, JobContact, Opportunity};
+use crate::servicem8::{Filter, TableFilters, Tables};
+use crate::snapshot::{SnapshotStore, ACTIVITY, CLIENT, CONTACT, JOB, OPPORTUNITY};
+use crate::{current_rms, servicem8};
+
+const DEFAULT_FULL_SYNC_DAYS: i64 = 7;
+
+// Anything changed within the same second as the last record we saw may not
+// have been downloaded, so each sync overlaps the one before by this much.
+const OVERLAP_SECONDS: i64 = 1;
+
+trait Synced: Serialize + DeserializeOwned {
+    fn key(&self) -> String;
+    fn edited_at(&self) -> Option<DateTime<Utc>>;
+    fn active(&self) -> bool;
+}
+
+impl Synced for Opportunity {
+    fn key(&self) -> String {
+        self.id.to_string()
+    }
+    fn edited_at(&self) -> Option<DateTime<Utc>> {
+        self.updated_at
+    }
+    // Opportunities are deleted outright rather than made inactive.
+    fn active(&self) -> bool {
+        true
+    }
+}
+
+macro_rules! servicem8_synced {
+    ($($record:ty),*) => {
+        $(impl Synced for $record {
+            fn key(&self) -> String {
+                self.uuid.clone()
+            }
+            fn edited_at(&self) -> Option<DateTime<Utc>> {
+                self.edit_date
+            }
+            fn active(&self) -> bool {
+                self.active
+            }
+        })*
+    };
+}
+
+servicem8_synced!(Company, Job, JobActivity, JobContact);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    // Only what has changed, unless a full sync is due.
+    Incremental,
+    Full,
+}
+
+// What a sync did to one kind of record.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TableSync {
+    pub kind: &'static str,
+    // What the records were downloaded from, None for a full sync.
+    pub since: Option<DateTime<Utc>>,
+    pub downloaded: usize,
+    pub inactivated: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+impl fmt::Display for TableSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} downloaded", self.kind, self.downloaded)?;
+        match self.since {
+            Some(since) => write!(f, " since {}", since.format("%Y-%m-%d %H:%M:%S"))?,
+            None => write!(f, " in full")?,
+        }
+        write!(
+            f,
+            ", {} made inactive, {} deleted",
+            self.inactivated.len(),
+            self.deleted.len()
+        )
+    }
+}
+
+// Where the last sync of a kind of record got up to.
+struct SyncState {
+    watermark: Option<DateTime<Utc>>,
+    full_sync_at: DateTime<Utc>,
+}
+
+fn full_sync_interval() -> Result<Duration> {
+    match env::var("SYNC_FULL_DAYS") {
+        Ok(days) => days.trim().parse().map(Duration::days).map_err(|_| {
+            Error::Configuration(format!("SYNC_FULL_DAYS '{}' isn't a number of days", days))
+        }),
+        Err(_) => Ok(Duration::days(DEFAULT_FULL_SYNC_DAYS)),
+    }
+}
+
+fn sync_state(connection: &Connection, kind: &str) -> Result<Option<SyncState>> {
+    Ok(connection
+        .query_row(
+            "SELECT watermark, full_sync_at FROM syncs WHERE kind = ?1",
+            params![kind],
+            |row| {
+                Ok(SyncState {
+                    watermark: row.get(0)?,
+                    full_sync_at: row.get(1)?,
+                })
+            },
+        )
+        .optional()?)
+}
+
+// Stores the downloaded records of one kind and moves its watermark on, as part
+// of the transaction for the whole sync.
+fn apply<T: Synced>(
+    transaction: &Transaction<'_>,
+    kind: &'static str,
+    since: Option<DateTime<Utc>>,
+    records: &[T],
+    now: DateTime<Utc>,
+) -> Result<TableSync> {
+    let previous = sync_state(transaction, kind)?;
+    let full = since.is_none();
+
+    let mut inactivated = Vec::new();
+    for record in records.iter().filter(|record| !record.active()) {
+        if was_active(transaction, kind, &record.key())? {
+            inactivated.push(record.key());
+        }
+    }
+
+    let mut deleted = Vec::new();
+    if full {
+        let downloaded: HashSet<String> = records.iter().map(Synced::key).collect();
+        let stored = {
+            let mut select =
+                transaction.prepare("SELECT key FROM synced_records WHERE kind = ?1")?;
+            let keys = select.query_map(params![kind], |row| row.get::<_, String>(0))?;
+            keys.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        deleted = stored
+            .into_iter()
+            .filter(|key| !downloaded.contains(key))
+            .collect();
+        for key in &deleted {
+            transaction.execute(
+                "DELETE FROM synced_records WHERE kind = ?1 AND key = ?2",
+                params![kind, key],
+            )?;
+        }
+    }
+
+    {
+        let mut upsert = transaction.prepare(
+            "INSERT OR REPLACE INTO synced_records (kind, key, record, active)
+             VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        for record in records {
+            upsert.execute(params![
+                kind,
+                record.key(),
+                serde_json::to_string(record)?,
+                record.active()
+            ])?;
+        }
+    }
+
+    let latest = records.iter().filter_map(Synced::edited_at).max();
+    let (watermark, full_sync_at) = match (full, previous) {
+        (false, Some(previous)) => (latest.max(previous.watermark), previous.full_sync_at),
+        _ => (latest, now),
+    };
+    transaction.execute(
+        "INSERT OR REPLACE INTO syncs (kind, watermark, full_sync_at, synced_at)
+         VALUES (?1, ?2, ?3, ?4)",
+        params![kind, watermark, full_sync_at, now],
+    )?;
+
+    Ok(TableSync {
+        kind,
+        since,
+        downloaded: records.len(),
+        inactivated,
+        deleted,
+    })
+}
+
+impl SnapshotStore {
+    // The time to download changes from, or None when everything has to be
+    // downloaded: the first time, when asked to, or when a full sync is due.
+    fn changed_since(
+        &self,
+        kind: &str,
+        mode: Mode,
+        interval: Duration,
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>> {
+        if mode == Mode::Full {
+            return Ok(None);
+        }
+        Ok(match sync_state(&self.connection, kind)? {
+            Some(state) if now - state.full_sync_at < interval => state
+                .watermark
+                .map(|watermark| watermark - Duration::seconds(OVERLAP_SECONDS)),
+            _ => None,
+        })
+    }
+
+    // The synced copy of every record, opportunities in order of their id.
+    pub fn synced(&self) -> Result<(Vec<Opportunity>, Tables)> {
+        let mut opportunities: Vec<Opportunity> = self.synced_records(OPPORTUNITY)?;
+        opportunities.sort_by_key(|opportunity| opportunity.id);
+        let tables = Tables {
+            clients: self.synced_records(CLIENT)?,
+            jobs: self.synced_records(JOB)?,
+            activities: self.synced_records(ACTIVITY)?,
+            contacts: self.synced_records(CONTACT)?,
+        };
+        Ok((opportunities, tables))
+    }
+
+    fn synced_records<T: DeserializeOwned>(&self, kind: &str) -> Result<Vec<T>> {
+        let mut select = self
+            .connection
+            .prepare("SELECT record FROM synced_records WHERE kind = ?1 ORDER BY key")?;
+        let rows = select.query_map(params![kind], |row| row.get::<_, String>(0))?;
+        let mut records = Vec::new();
+        for row in rows {
+            records.push(serde_json::from_str(&row?)?);
+        }
+        Ok(records)
+    }
+}
+
+fn was_active(transaction: &Transaction<'_>, kind: &str, key: &str) -> Result<bool> {
+    let active: Option<bool> = transaction
+        .query_row(
+            "SELECT active FROM synced_records WHERE kind = ?1 AND key = ?2",
+            params![kind, key],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(active.unwrap_or(false))
+}
+
+fn edited_since(since: Option<DateTime<Utc>>) -> Filter {
+    match since {
+        Some(since) => Filter::new().edited_after(since),
+        None => Filter::new(),
+    }
+}
+
+// Downloads whatever has changed in both services into the store.  Nothing is
+// stored until every download has succeeded, and then it is all stored in a
+// single transaction.
+pub fn sync(
+    auth_cache: &AuthenticationCache,
+    store: &mut SnapshotStore,
+    mode: Mode,
+) -> Result<Vec<TableSync>> {
+    let interval = full_sync_interval()?;
+    let now = Utc::now();
+    let since = |kind| store.changed_since(kind, mode, interval, now);
+    let (opportunities_since, clients_since, jobs_since, activities_since, contacts_since) = (
+        since(OPPORTUNITY)?,
+        since(CLIENT)?,
+        since(JOB)?,
+        since(ACTIVITY)?,
+        since(CONTACT)?,
+    );
+
+    let opportunities = match opportunities_since {
+        Some(since) => current_rms::opportunities_matching(
+            auth_cache,
+            &current_rms::Query::new().updated_from(&since),
+        )?,
+        None => current_rms::opportunities(auth_cache)?,
+    };
+    let filters = TableFilters {
+        clients: Some(edited_since(clients_since)),
+        jobs: Some(edited_since(jobs_since)),
+        activities: Some(edited_since(activities_since)),
+        contacts: Some(edited_since(contacts_since)),
+    };
+    let tables = servicem8::tables_matching(auth_cache, &filters)?;
+
+    // Every kind is stored together, so that the copy is never left with some
+    // kinds synced and others not.
+    let transaction = store.connection.transaction()?;
+    let synced = vec![
+        apply(
+            &transaction,
+            OPPORTUNITY,
+            opportunities_since,
+            &opportunities,
+            now,
+        )?,
+        apply(&transaction, CLIENT, clients_since, &tables.clients, now)?,
+        apply(&transaction, JOB, jobs_since, &tables.jobs, now)?,
+        apply(
+            &transaction,
+            ACTIVITY,
+            activities_since,
+            &tables.activities,
+            now,
+        )?,
+        apply(&transaction, CONTACT, contacts_since, &tables.contacts, now)?,
+    ];
+    transaction.commit()?;
+    Ok(synced)
+}
@@ -0,0 +1,134 @@
+// Regression tests which replay recorded traffic, so they run without network
+// access or credentials.  The cassettes are recorded against the fake server by
+// running `cargo test -p schedule_assistant --test cassettes -- --ignored`, or
+// can be recorded against the real services with HTTP_CASSETTE_MODE=record.
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use fake_server::{FakeServer, Fixtures};
+use schedule_assistant::authentication::AuthenticationCache;
+use schedule_assistant::config::ServiceUrls;
+use schedule_assistant::links::LinkStore;
+use schedule_assistant::{current_rms, models, servicem8, Explanation, Mismatch};
+use std::env;
+use std::path::PathBuf;
+
+fn cassette(name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), "tests", "cassettes", name]
+        .iter()
+        .collect::<PathBuf>()
+        .with_extension("json")
+}
+
+// Only the paths matter when replaying, they match those of the fake server.
+fn replay(name: &str) -> AuthenticationCache {
+    let urls = ServiceUrls {
+        current_rms_api: String::from("http://localhost/current/api/v1"),
+        current_rms_web: String::from("http://localhost/current/web"),
+        servicem8_api: String::from("http://localhost/servicem8/api_1.0"),
+    };
+    AuthenticationCache::replay(urls, &cassette(name)).unwrap()
+}
+
+fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+type Scenario = fn(&AuthenticationCache);
+
+fn check_bookings(auth_cache: &AuthenticationCache) {
+    let unscheduled =
+        schedule_assistant::check_bookings(auth_cache, date(2021, 3, 22), date(2021, 3, 29))
+            .unwrap();
+    let ids: Vec<u64> = unscheduled
+        .iter()
+        .map(|opportunity| opportunity.id)
+        .collect();
+    assert_eq!(ids, [102]);
+}
+
+fn opportunity_has_job(auth_cache: &AuthenticationCache) {
+    let query = current_rms::Query::new().state(models::STATE_ORDER);
+    let opportunities = current_rms::opportunities_matching(auth_cache, &query).unwrap();
+    let tables = servicem8::tables(auth_cache).unwrap();
+    let has_job = |id: u64| {
+        let opportunity = opportunities.iter().find(|o| o.id == id).unwrap();
+        schedule_assistant::opportunity_has_job(
+            &LinkStore::default(),
+            opportunity,
+            &tables.jobs,
+            &tables.clients,
+            &tables.contacts,
+            &tables.activities,
+        )
+        .unwrap()
+    };
+    assert!(has_job(101));
+    assert!(!has_job(102));
+
+    // Jane Smith's job is listed as a contact mismatch for Acme Limited.
+    let acme = opportunities.iter().find(|o| o.id == 102).unwrap();
+    let explanation = schedule_assistant::explain_opportunity(
+        &LinkStore::default(),
+        acme,
+        &tables.jobs,
+        &tables.clients,
+        &tables.contacts,
+        &tables.activities,
+    )
+    .unwrap();
+    match explanation {
+        Explanation::Unmatched(mismatches) => {
+            assert_eq!(mismatches.len(), 1);
+            assert!(matches!(mismatches[0].1, Mismatch::Contacts { .. }));
+        }
+        explanation => panic!("expected no match, got {:?}", explanation),
+    }
+}
+
+fn remove_expired_quotes(auth_cache: &AuthenticationCache) {
+    let date = Utc.with_ymd_and_hms(2021, 3, 10, 0, 0, 0).unwrap();
+    let failures = schedule_assistant::remove_expired_quotes(auth_cache, date).unwrap();
+    assert!(failures.is_empty(), "{:?}", failures);
+}
+
+#[test]
+fn check_bookings_finds_the_unscheduled_order() {
+    check_bookings(&replay("check_bookings"));
+}
+
+#[test]
+fn opportunity_has_job_matches_by_client_and_dates() {
+    opportunity_has_job(&replay("opportunity_has_job"));
+}
+
+#[test]
+fn remove_expired_quotes_marks_the_quote_as_dead() {
+    let auth_cache = replay("remove_expired_quotes");
+    remove_expired_quotes(&auth_cache);
+    // Including the mark_as_dead for 103.
+    assert!(
+        auth_cache.unplayed().is_empty(),
+        "{:?}",
+        auth_cache.unplayed()
+    );
+}
+
+#[test]
+#[ignore]
+fn record_cassettes() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    for (name, value) in server.env() {
+        env::set_var(name, value);
+    }
+    env::set_var("HTTP_CASSETTE_MODE", "record");
+
+    let scenarios: [(&str, Scenario); 3] = [
+        ("check_bookings", check_bookings),
+        ("opportunity_has_job", opportunity_has_job),
+        ("remove_expired_quotes", remove_expired_quotes),
+    ];
+    for (name, scenario) in scenarios {
+        env::set_var("HTTP_CASSETTE", cassette(name));
+        scenario(&AuthenticationCache::new().unwrap());
+    }
+}
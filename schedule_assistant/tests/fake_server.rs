@@ -0,0 +1,63 @@
+// Tests which talk to the fake server directly through the library.  The
+// services are configured through the environment, which is shared by every
+// test in this file, so there is a single test making its checks in turn.
+
+use fake_server::{FakeServer, Fixtures};
+use schedule_assistant::authentication::AuthenticationCache;
+use schedule_assistant::current_rms::{self, Query};
+use std::env;
+
+static OPPORTUNITIES: &str = "/current/api/v1/opportunities";
+
+// How many pages of opportunities have been requested.
+fn pages_requested(server: &FakeServer) -> usize {
+    server
+        .requests()
+        .iter()
+        .filter(|(method, path)| method == "GET" && path == OPPORTUNITIES)
+        .count()
+}
+
+#[test]
+fn opportunity_pages_are_requested_as_they_are_needed() {
+    let fixtures = Fixtures::examples().unwrap();
+    let ids: Vec<u64> = fixtures
+        .opportunities
+        .iter()
+        .filter_map(|opportunity| opportunity["id"].as_u64())
+        .collect();
+    assert!(ids.len() > 1);
+
+    let server = FakeServer::start(fixtures).unwrap();
+    for (name, value) in server.env() {
+        env::set_var(name, value);
+    }
+    let auth_cache = AuthenticationCache::new().unwrap();
+
+    // Stopping after the first opportunity only downloads the first page.
+    let first: Vec<u64> = current_rms::opportunity_pages(&auth_cache, &Query::new())
+        .per_page(1)
+        .take(1)
+        .map(|opportunity| opportunity.unwrap().id)
+        .collect();
+    assert_eq!(first, ids[..1]);
+    assert_eq!(pages_requested(&server), 1);
+
+    // Going all the way through stops at the last page rather than asking for
+    // one past it.
+    let all: Vec<u64> = current_rms::opportunity_pages(&auth_cache, &Query::new())
+        .per_page(1)
+        .map(|opportunity| opportunity.unwrap().id)
+        .collect();
+    assert_eq!(all, ids);
+    assert_eq!(pages_requested(&server), 1 + ids.len());
+
+    // As it does when the last page is a short one.
+    let before = pages_requested(&server);
+    let all: Vec<u64> = current_rms::opportunity_pages(&auth_cache, &Query::new())
+        .per_page(2)
+        .map(|opportunity| opportunity.unwrap().id)
+        .collect();
+    assert_eq!(all, ids);
+    assert_eq!(pages_requested(&server) - before, ids.len().div_ceil(2));
+}
@@ -0,0 +1,479 @@
+use schedule_assistant::matching::{self, Matcher};
+use schedule_assistant::models::{Company, Job, JobActivity, JobContact, Opportunity};
+use serde_json::json;
+
+fn opportunity(name: &str) -> Opportunity {
+    serde_json::from_value(json!({
+        "id": 1,
+        "member": {
+            "id": 1,
+            "name": name,
+            "emails": [{ "id": 1, "address": "Jane@Example.com" }],
+            "phones": [{ "id": 1, "number": "+64 21 000 0000" }]
+        },
+        "starts_at": "2021-03-23T20:00:00.000Z",
+        "ends_at": "2021-03-25T04:00:00.000Z",
+        "state": 3,
+        "destination": {
+            "address": { "street": "1 Queen Street", "city": "Auckland", "postcode": "1010" }
+        }
+    }))
+    .unwrap()
+}
+
+fn job(uuid: &str, client: &str) -> Job {
+    serde_json::from_value(json!({
+        "uuid": uuid,
+        "company_uuid": client,
+        "job_address": "1 Queen Street, Auckland 1010"
+    }))
+    .unwrap()
+}
+
+fn client(uuid: &str, name: &str) -> Company {
+    serde_json::from_value(json!({ "uuid": uuid, "name": name })).unwrap()
+}
+
+fn contact(job: &str, first: &str, last: &str, email: &str, mobile: &str) -> JobContact {
+    serde_json::from_value(json!({
+        "uuid": format!("{}-contact", job),
+        "job_uuid": job,
+        "first": first,
+        "last": last,
+        "email": email,
+        "mobile": mobile
+    }))
+    .unwrap()
+}
+
+fn activity(job: &str, start: &str, end: &str) -> JobActivity {
+    serde_json::from_value(json!({
+        "uuid": format!("{}-activity", job),
+        "job_uuid": job,
+        "start_date": start,
+        "end_date": end
+    }))
+    .unwrap()
+}
+
+#[test]
+fn names_are_compared_loosely() {
+    assert!(matching::same_name("Jane Smith", "Smith, Jane"));
+    assert!(matching::same_name("Jane Smith", "JANE SMITH"));
+    assert!(matching::same_name("Jane Smith", "Jane Smtih"));
+    assert!(matching::same_name("Smith, Jane", "Jane Smith-Jones"));
+    assert!(matching::same_name("Acme Ltd", "Acme Limited"));
+    assert!(matching::same_name("J Smith", "Jane Smith"));
+    assert!(!matching::same_name("Jane Smith", "John Smith"));
+    assert!(!matching::same_name("Mark Smith", "Mary Smith"));
+    assert!(!matching::same_name("Jon Smith", "Joan Smith"));
+    assert!(!matching::same_name("Smith, Mark", "Mary Smith"));
+    assert!(!matching::same_name("Jane Smith", "Jane"));
+    assert!(!matching::same_name("", ""));
+}
+
+#[test]
+fn emails_and_phones_are_normalised() {
+    assert!(matching::same_email(
+        " Jane@Example.com",
+        "jane@example.com"
+    ));
+    assert!(!matching::same_email("", ""));
+    assert_eq!(
+        matching::normalize_phone("+64 21 000 0000"),
+        matching::normalize_phone("021-000-0000")
+    );
+    assert_eq!(matching::normalize_phone("123"), None);
+}
+
+#[test]
+fn candidates_are_ranked_by_confidence() {
+    let jobs = [
+        job("wrong-dates", "c1"),
+        job("right", "c1"),
+        job("someone-else", "c2"),
+    ];
+    let clients = [client("c1", "Smith, Jane"), client("c2", "Bob Jones")];
+    let contacts = [contact(
+        "right",
+        "Jane",
+        "Smith-Jones",
+        "jane@example.com",
+        "021 000 0000",
+    )];
+    let activities = [
+        activity("wrong-dates", "2021-02-01 08:00:00", "2021-02-01 10:00:00"),
+        activity("right", "2021-03-24 08:00:00", "2021-03-24 10:00:00"),
+        activity("someone-else", "2021-03-24 08:00:00", "2021-03-24 10:00:00"),
+    ];
+    let opportunity = opportunity("Jane Smith");
+
+    let matcher = Matcher::new(&jobs, &clients, &contacts, &activities);
+    let candidates = matcher.candidates(&opportunity);
+    let uuids: Vec<&str> = candidates.iter().map(|c| c.job.uuid.as_str()).collect();
+    assert_eq!(uuids, ["right"]);
+    let best = &candidates[0];
+    assert_eq!(best.scores.email, Some(1.0));
+    assert_eq!(best.scores.phone, Some(1.0));
+    assert_eq!(best.scores.address, Some(1.0));
+    assert_eq!(best.scores.dates, 1.0);
+
+    // Lowering the threshold includes the job on the wrong dates, but after the
+    // right one.
+    let candidates = matcher.threshold(0.65).candidates(&opportunity);
+    let uuids: Vec<&str> = candidates.iter().map(|c| c.job.uuid.as_str()).collect();
+    assert_eq!(uuids, ["right", "wrong-dates"]);
+}
+
+#[test]
+fn a_similar_name_with_a_different_first_name_is_someone_else() {
+    let jobs = [job("mary", "c1")];
+    let clients = [client("c1", "Mary Smith")];
+    let activities = [activity(
+        "mary",
+        "2021-03-24 08:00:00",
+        "2021-03-24 10:00:00",
+    )];
+    let opportunity = opportunity("Mark Smith");
+
+    // Same dates and address, but without any contact details in common.
+    let matcher = Matcher::new(&jobs, &clients, &[], &activities);
+    let candidate = matcher.score(&opportunity, &jobs[0]);
+    assert_eq!(candidate.scores.name, 0.0);
+    assert!(matcher.best(&opportunity).is_none());
+}
+
+#[test]
+fn stored_references_are_preferred_over_matching() {
+    use schedule_assistant::links::LinkStore;
+    use schedule_assistant::{Explanation, MatchSource, Mismatch};
+
+    // Neither job could be matched by name, but one refers to the opportunity
+    // and the other to a different one.
+    let mut jobs = [job("referenced", "c2"), job("other", "c1")];
+    jobs[0].purchase_order_number = String::from("RMS#1");
+    jobs[1].job_description = String::from("Marquee\nRMS#2");
+    let clients = [client("c1", "Jane Smith"), client("c2", "Bob Jones")];
+    let activities = [
+        activity("referenced", "2021-03-23 08:00:00", "2021-03-23 10:00:00"),
+        activity("referenced", "2021-03-25 15:00:00", "2021-03-25 17:00:00"),
+    ];
+    let opportunity = opportunity("Jane Smith");
+
+    let explain = |jobs: &[Job]| {
+        schedule_assistant::explain_opportunity(
+            &LinkStore::default(),
+            &opportunity,
+            jobs,
+            &clients,
+            &[],
+            &activities,
+        )
+        .unwrap()
+    };
+    assert_eq!(
+        explain(&jobs),
+        Explanation::Matched {
+            job: String::from("referenced"),
+            source: MatchSource::Stored
+        }
+    );
+    assert_eq!(
+        explain(&jobs[1..]),
+        Explanation::Unmatched(vec![(
+            String::from("other"),
+            Mismatch::OtherOpportunity { opportunity: 2 }
+        )])
+    );
+}
+
+#[test]
+fn a_linked_or_stored_job_still_needs_both_legs() {
+    use chrono::NaiveDate;
+    use schedule_assistant::links::LinkStore;
+    use schedule_assistant::{Explanation, Mismatch};
+
+    // Neither job is the member's by name, so only the link or the reference
+    // ties it to the opportunity.
+    let mut jobs = [job("linked", "c2"), job("stored", "c2")];
+    jobs[1].purchase_order_number = String::from("RMS#1");
+    let clients = [client("c2", "Bob Jones")];
+    let activities = [
+        activity("linked", "2021-03-23 08:00:00", "2021-03-23 10:00:00"),
+        activity("stored", "2021-03-23 08:00:00", "2021-03-23 10:00:00"),
+    ];
+    let opportunity = opportunity("Jane Smith");
+    let missing_collection = |job: &str| {
+        Explanation::Unmatched(vec![(
+            String::from(job),
+            Mismatch::MissingCollection {
+                expected: NaiveDate::from_ymd_opt(2021, 3, 25).unwrap(),
+            },
+        )])
+    };
+
+    let mut links = LinkStore::default();
+    links.add(1, "linked", "test").unwrap();
+    let explain = |links: &LinkStore| {
+        schedule_assistant::explain_opportunity(
+            links,
+            &opportunity,
+            &jobs,
+            &clients,
+            &[],
+            &activities,
+        )
+        .unwrap()
+    };
+    assert_eq!(explain(&links), missing_collection("linked"));
+    assert_eq!(explain(&LinkStore::default()), missing_collection("stored"));
+}
+
+#[test]
+fn dataset_indexes_records_by_job() {
+    use schedule_assistant::dataset::Dataset;
+
+    let jobs = [job("right", "c1"), job("someone-else", "c2")];
+    let clients = [client("c1", "Smith, Jane"), client("c2", "Bob Jones")];
+    let contacts = [contact("right", "Jane", "Smith", "", "")];
+    let activities = [
+        activity("right", "2021-03-23 08:00:00", "2021-03-23 10:00:00"),
+        activity("right", "2021-03-25 15:00:00", "2021-03-25 17:00:00"),
+        activity("someone-else", "2021-03-24 08:00:00", "2021-03-24 10:00:00"),
+    ];
+    let dataset = Dataset::new(&jobs, &clients, &contacts, &activities);
+
+    assert_eq!(dataset.client(&jobs[0]).unwrap().uuid, "c1");
+    assert_eq!(dataset.contacts(&jobs[0]).len(), 1);
+    assert!(dataset.contacts(&jobs[1]).is_empty());
+    assert_eq!(dataset.activities(&jobs[0]).len(), 2);
+    assert_eq!(dataset.activities(&jobs[1]).len(), 1);
+
+    let opportunities = [opportunity("Jane Smith")];
+    assert!(dataset
+        .find_opportunity_for_job(&opportunities, &jobs[0])
+        .is_some());
+    assert!(dataset
+        .find_opportunity_for_job(&opportunities, &jobs[1])
+        .is_none());
+}
+
+#[test]
+fn a_job_referring_to_another_opportunity_is_not_matched_to_this_one() {
+    let mut jobs = [job("right", "c1")];
+    jobs[0].purchase_order_number = String::from("RMS#2");
+    let clients = [client("c1", "Jane Smith")];
+    let activities = [
+        activity("right", "2021-03-23 08:00:00", "2021-03-23 10:00:00"),
+        activity("right", "2021-03-25 15:00:00", "2021-03-25 17:00:00"),
+    ];
+    let job_activities: Vec<&JobActivity> = activities.iter().collect();
+    let find = |opportunities: &[Opportunity]| {
+        schedule_assistant::find_opportunity_for_job(
+            opportunities,
+            &jobs[0],
+            &clients[0],
+            &[],
+            &job_activities,
+        )
+        .map(|opportunity| opportunity.id)
+    };
+
+    // The job would match by name and dates, but it is opportunity 2's.
+    let mut opportunities = vec![opportunity("Jane Smith")];
+    assert_eq!(find(&opportunities), None);
+
+    let mut other = opportunity("Bob Jones");
+    other.id = 2;
+    opportunities.push(other);
+    assert_eq!(find(&opportunities), Some(2));
+}
+
+#[test]
+fn both_legs_must_be_scheduled() {
+    use chrono::NaiveDate;
+    use schedule_assistant::links::LinkStore;
+    use schedule_assistant::{Explanation, Leg, Mismatch};
+
+    let jobs = [job("right", "c1")];
+    let clients = [client("c1", "Jane Smith")];
+    let delivery = activity("right", "2021-03-23 08:00:00", "2021-03-23 10:00:00");
+    let collection = activity("right", "2021-03-25 15:00:00", "2021-03-25 17:00:00");
+    let too_late = activity("right", "2021-04-10 08:00:00", "2021-04-10 10:00:00");
+    let opportunity = opportunity("Jane Smith");
+
+    assert_eq!(
+        schedule_assistant::classify_activity(&opportunity, &delivery),
+        Some(Leg::Delivery)
+    );
+    assert_eq!(
+        schedule_assistant::classify_activity(&opportunity, &collection),
+        Some(Leg::Collection)
+    );
+    assert_eq!(
+        schedule_assistant::classify_activity(&opportunity, &too_late),
+        None
+    );
+
+    let explain = |activities: &[JobActivity]| {
+        schedule_assistant::explain_opportunity(
+            &LinkStore::default(),
+            &opportunity,
+            &jobs,
+            &clients,
+            &[],
+            activities,
+        )
+        .unwrap()
+    };
+    assert!(matches!(
+        explain(&[delivery.clone(), collection.clone()]),
+        Explanation::Matched { .. }
+    ));
+    assert_eq!(
+        explain(&[delivery, too_late]),
+        Explanation::Unmatched(vec![(
+            String::from("right"),
+            Mismatch::MissingCollection {
+                expected: NaiveDate::from_ymd_opt(2021, 3, 25).unwrap()
+            }
+        )])
+    );
+    assert_eq!(
+        explain(&[collection]),
+        Explanation::Unmatched(vec![(
+            String::from("right"),
+            Mismatch::MissingDelivery {
+                expected: NaiveDate::from_ymd_opt(2021, 3, 23).unwrap()
+            }
+        )])
+    );
+}
+
+#[test]
+fn drifted_activities_are_rescheduled() {
+    use chrono::NaiveDate;
+    use schedule_assistant::reconcile::{self, Drift};
+    use schedule_assistant::Leg;
+
+    // The event runs from 2021-03-23 20:00 to 2021-03-25 04:00.
+    let mut opportunity = opportunity("Jane Smith");
+    let on_time = [
+        activity("job", "2021-03-23 08:00:00", "2021-03-23 10:00:00"),
+        activity("job", "2021-03-25 15:00:00", "2021-03-25 17:00:00"),
+    ];
+    let drifted = [
+        activity("job", "2021-03-24 08:00:00", "2021-03-24 10:00:00"),
+        activity("job", "2021-03-25 02:00:00", "2021-03-25 03:00:00"),
+    ];
+    let reconcile = |opportunity: &Opportunity, activities: &[JobActivity]| {
+        let activities: Vec<&JobActivity> = activities.iter().collect();
+        reconcile::reconcile_job(opportunity, "job", &activities)
+    };
+
+    assert!(reconcile(&opportunity, &on_time).is_empty());
+
+    let reschedules = reconcile(&opportunity, &drifted);
+    let drifts: Vec<(Leg, Drift)> = reschedules
+        .iter()
+        .map(|r| (r.leg, r.drift.clone()))
+        .collect();
+    assert_eq!(
+        drifts,
+        [
+            (Leg::Delivery, Drift::LateDelivery),
+            (Leg::Collection, Drift::EarlyCollection)
+        ]
+    );
+
+    // Booking the delivery for the day before moves where it's expected.
+    opportunity.deliver_starts_at = Some("2021-03-22T08:00:00Z".parse().unwrap());
+    let reschedules = reconcile(&opportunity, &on_time[..1]);
+    assert_eq!(reschedules.len(), 1);
+    assert_eq!(reschedules[0].drift, Drift::WrongDay);
+    assert_eq!(
+        reschedules[0].expected,
+        NaiveDate::from_ymd_opt(2021, 3, 22).unwrap()
+    );
+}
+
+#[test]
+fn a_job_without_its_client_does_not_stop_the_others_matching() {
+    use schedule_assistant::links::LinkStore;
+    use schedule_assistant::{Explanation, Mismatch};
+
+    let jobs = [job("orphan", "gone"), job("right", "c1")];
+    let clients = [client("c1", "Jane Smith")];
+    let activities = [
+        activity("orphan", "2021-03-23 08:00:00", "2021-03-23 10:00:00"),
+        activity("right", "2021-03-23 08:00:00", "2021-03-23 10:00:00"),
+        activity("right", "2021-03-25 15:00:00", "2021-03-25 17:00:00"),
+    ];
+    let opportunity = opportunity("Jane Smith");
+
+    let explain = |jobs: &[Job]| {
+        schedule_assistant::explain_opportunity(
+            &LinkStore::default(),
+            &opportunity,
+            jobs,
+            &clients,
+            &[],
+            &activities,
+        )
+        .unwrap()
+    };
+    assert!(matches!(explain(&jobs), Explanation::Matched { job, .. } if job == "right"));
+    assert_eq!(
+        explain(&jobs[..1]),
+        Explanation::Unmatched(vec![(
+            String::from("orphan"),
+            Mismatch::MissingClient {
+                client: String::from("gone")
+            }
+        )])
+    );
+}
+
+#[test]
+fn the_job_to_reconcile_need_not_be_near_the_booking() {
+    use schedule_assistant::dataset::Dataset;
+    use schedule_assistant::links::LinkStore;
+
+    let mut jobs = [
+        job("drifted", "c1"),
+        job("last-year", "c1"),
+        job("other-booking", "c1"),
+        job("someone-else", "c2"),
+    ];
+    jobs[2].purchase_order_number = String::from("RMS#2");
+    let clients = [client("c1", "Jane Smith"), client("c2", "Bob Jones")];
+    let activities = [
+        activity("drifted", "2021-04-02 08:00:00", "2021-04-02 10:00:00"),
+        activity("last-year", "2020-03-24 08:00:00", "2020-03-24 10:00:00"),
+        activity(
+            "other-booking",
+            "2021-03-24 08:00:00",
+            "2021-03-24 10:00:00",
+        ),
+        activity("someone-else", "2021-03-24 08:00:00", "2021-03-24 10:00:00"),
+    ];
+    let dataset = Dataset::new(&jobs, &clients, &[], &activities);
+    let opportunity = opportunity("Jane Smith");
+    let uuid = |links: &LinkStore, jobs: &Dataset| {
+        jobs.job_to_reconcile(links, &opportunity)
+            .map(|job| job.uuid.clone())
+    };
+
+    // A week out is too far for the job to be matched, but not to reconcile.
+    let links = LinkStore::default();
+    assert_eq!(uuid(&links, &dataset), Some(String::from("drifted")));
+
+    // Without it there's nothing close enough to be this booking.
+    let without = Dataset::new(&jobs[1..], &clients, &[], &activities);
+    assert_eq!(uuid(&links, &without), None);
+
+    // A linked job is used wherever it is.
+    let mut links = LinkStore::default();
+    links.add(1, "last-year", "test").unwrap();
+    assert_eq!(uuid(&links, &dataset), Some(String::from("last-year")));
+}
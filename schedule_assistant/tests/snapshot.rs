@@ -0,0 +1,201 @@
+use schedule_assistant::models::{Company, Job, JobActivity, Opportunity};
+use schedule_assistant::servicem8::Tables;
+use schedule_assistant::snapshot::{self, Change, Record, SnapshotStore};
+use serde_json::json;
+
+fn opportunity(id: u64, state: u32, street: &str) -> Opportunity {
+    serde_json::from_value(json!({
+        "id": id,
+        "subject": format!("Hire {}", id),
+        "starts_at": "2021-03-23T20:00:00.000Z",
+        "ends_at": "2021-03-25T04:00:00.000Z",
+        "state": state,
+        "destination": { "address": { "street": street, "city": "Auckland" } }
+    }))
+    .unwrap()
+}
+
+fn tables(client_address: &str, activities: &[(&str, &str)]) -> Tables {
+    let client: Company = serde_json::from_value(json!({
+        "uuid": "c1",
+        "name": "Jane Smith",
+        "address": client_address
+    }))
+    .unwrap();
+    let job: Job = serde_json::from_value(json!({
+        "uuid": "j1",
+        "company_uuid": "c1",
+        "generated_job_id": "1001"
+    }))
+    .unwrap();
+    let activities = activities
+        .iter()
+        .map(|(uuid, start)| {
+            serde_json::from_value::<JobActivity>(json!({
+                "uuid": uuid,
+                "job_uuid": "j1",
+                "start_date": format!("{} 08:00:00", start),
+                "end_date": format!("{} 10:00:00", start)
+            }))
+            .unwrap()
+        })
+        .collect();
+    Tables {
+        clients: vec![client],
+        jobs: vec![job],
+        activities,
+        contacts: Vec::new(),
+    }
+}
+
+#[test]
+fn snapshots_are_read_back_as_they_were_saved() {
+    let mut store = SnapshotStore::in_memory().unwrap();
+    assert!(store.latest().unwrap().is_none());
+
+    store
+        .save(vec![opportunity(1, 3, "1 Queen Street")], Tables::default())
+        .unwrap();
+    let saved = store
+        .save(
+            vec![opportunity(1, 3, "1 Queen Street"), opportunity(2, 2, "")],
+            tables("1 Queen Street", &[("a1", "2021-03-23")]),
+        )
+        .unwrap();
+
+    let latest = store.latest().unwrap().unwrap();
+    assert_eq!(latest.id, saved.id);
+    assert_eq!(latest.taken_at, saved.taken_at);
+    let ids: Vec<u64> = latest.opportunities.iter().map(|op| op.id).collect();
+    assert_eq!(ids, vec![1, 2]);
+    assert_eq!(latest.tables.activities[0].uuid, "a1");
+    assert_eq!(store.list().unwrap().len(), 2);
+    assert!(snapshot::diff(&saved, &latest).is_empty());
+}
+
+#[test]
+fn deleted_orders_and_changed_records_are_reported() {
+    let mut store = SnapshotStore::in_memory().unwrap();
+    let before = store
+        .save(
+            vec![opportunity(1, 3, "1 Queen Street"), opportunity(2, 3, "")],
+            tables(
+                "1 Queen Street",
+                &[("a1", "2021-03-23"), ("a2", "2021-03-25")],
+            ),
+        )
+        .unwrap();
+    let after = store
+        .save(
+            vec![opportunity(1, 3, "3 Queen Street")],
+            tables(
+                "3 Queen Street",
+                &[("a1", "2021-03-23"), ("a3", "2021-03-26")],
+            ),
+        )
+        .unwrap();
+
+    let changes = snapshot::diff(&before, &after);
+    assert_eq!(changes.len(), 5, "{:#?}", changes);
+    assert!(changes.contains(&Change::AddressChanged {
+        record: Record::Opportunity(1),
+        from: String::from("1 Queen Street, Auckland"),
+        to: String::from("3 Queen Street, Auckland"),
+    }));
+    assert!(changes.contains(&Change::Cancelled {
+        opportunity: 2,
+        subject: String::from("Hire 2"),
+    }));
+    assert!(changes.contains(&Change::AddressChanged {
+        record: Record::Client(String::from("Jane Smith")),
+        from: String::from("1 Queen Street"),
+        to: String::from("3 Queen Street"),
+    }));
+    let moved: Vec<(&str, bool, bool)> = changes
+        .iter()
+        .filter_map(|change| match change {
+            Change::ActivityMoved {
+                activity, from, to, ..
+            } => Some((activity.as_str(), from.is_some(), to.is_some())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(moved, vec![("a3", false, true), ("a2", true, false)]);
+}
+
+#[test]
+fn earlier_snapshots_are_unchanged_by_later_ones() {
+    let mut store = SnapshotStore::in_memory().unwrap();
+    let first = store
+        .save(
+            vec![opportunity(1, 3, "1 Queen Street"), opportunity(2, 3, "")],
+            tables("1 Queen Street", &[("a1", "2021-03-23")]),
+        )
+        .unwrap();
+    // Opportunity 2 goes and comes back, and the client moves in between.
+    store
+        .save(
+            vec![opportunity(1, 3, "1 Queen Street")],
+            tables("3 Queen Street", &[("a1", "2021-03-23")]),
+        )
+        .unwrap();
+    let third = store
+        .save(
+            vec![opportunity(1, 3, "1 Queen Street"), opportunity(2, 3, "")],
+            tables("1 Queen Street", &[("a1", "2021-03-23")]),
+        )
+        .unwrap();
+
+    let ids = |id| {
+        let snapshot = store.load(id).unwrap().unwrap();
+        let ids: Vec<u64> = snapshot.opportunities.iter().map(|op| op.id).collect();
+        (ids, snapshot.tables.clients[0].address.clone())
+    };
+    assert_eq!(ids(first.id), (vec![1, 2], String::from("1 Queen Street")));
+    assert_eq!(ids(first.id + 1), (vec![1], String::from("3 Queen Street")));
+    assert_eq!(ids(third.id), (vec![1, 2], String::from("1 Queen Street")));
+    assert!(snapshot::diff(&first, &store.load(third.id).unwrap().unwrap()).is_empty());
+}
+
+#[test]
+fn only_the_records_which_changed_are_stored_again() {
+    let path =
+        std::env::temp_dir().join(format!("snapshot-versions-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut store = SnapshotStore::open(&path).unwrap();
+    let versions = || {
+        rusqlite::Connection::open(&path)
+            .unwrap()
+            .query_row("SELECT COUNT(*) FROM versions", [], |row| {
+                row.get::<_, i64>(0)
+            })
+            .unwrap()
+    };
+
+    // An opportunity, a client, a job and an activity.
+    let records = || {
+        (
+            vec![opportunity(1, 3, "1 Queen Street")],
+            tables("1 Queen Street", &[("a1", "2021-03-23")]),
+        )
+    };
+    let (opportunities, tables_now) = records();
+    store.save(opportunities, tables_now).unwrap();
+    assert_eq!(versions(), 4);
+
+    let (opportunities, tables_now) = records();
+    store.save(opportunities, tables_now).unwrap();
+    assert_eq!(versions(), 4);
+
+    let (opportunities, _) = records();
+    store
+        .save(
+            opportunities,
+            tables("1 Queen Street", &[("a1", "2021-03-24")]),
+        )
+        .unwrap();
+    assert_eq!(versions(), 5);
+
+    drop(store);
+    let _ = std::fs::remove_file(&path);
+}
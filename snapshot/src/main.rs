@@ -0,0 +1,125 @@
+// ---------------------------------------------------------------------
+// name: Snapshot
+// type: Command line application.
+// desc: Keeps a local copy of the current-rms opportunities and the
+//       servicem8 jobs, and lists what has changed between copies.
+// ---------------------------------------------------------------------
+//
+// usage:
+//   snapshot [take] [--full]  sync, save a new snapshot and list the changes
+//                             since the previous one
+//   snapshot sync [--full]    only bring the local copy of the records up to
+//                             date, --full downloads everything again
+//   snapshot diff [FROM TO]   list the changes between two snapshots, the last
+//                             two when they aren't given
+//   snapshot list
+
+use schedule_assistant::authentication::AuthenticationCache;
+use schedule_assistant::snapshot::{self, Change, SnapshotStore};
+use schedule_assistant::sync::{self, TableSync};
+use std::env;
+
+static USAGE: &str =
+    "usage: snapshot [take] [--full] | snapshot sync [--full] | snapshot diff [FROM TO] | snapshot list";
+
+enum Command {
+    Take { mode: sync::Mode },
+    Sync { mode: sync::Mode },
+    Diff { between: Option<(i64, i64)> },
+    List,
+}
+
+fn parse_command_line() -> anyhow::Result<Command> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let mode = if args.iter().any(|arg| arg == "--full") {
+        sync::Mode::Full
+    } else {
+        sync::Mode::Incremental
+    };
+    args.retain(|arg| arg != "--full");
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let snapshot = |id: &str| {
+        id.parse::<i64>()
+            .map_err(|_| anyhow::anyhow!("'{}' isn't a snapshot number", id))
+    };
+
+    match args.as_slice() {
+        [] | ["take"] => Ok(Command::Take { mode }),
+        ["sync"] => Ok(Command::Sync { mode }),
+        ["diff"] => Ok(Command::Diff { between: None }),
+        ["diff", from, to] => Ok(Command::Diff {
+            between: Some((snapshot(from)?, snapshot(to)?)),
+        }),
+        ["list"] => Ok(Command::List),
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+fn print_synced(synced: &[TableSync]) {
+    for table in synced {
+        println!("Synced {}", table);
+    }
+}
+
+fn print_changes(changes: &[Change]) {
+    if changes.is_empty() {
+        println!("Nothing has changed");
+    }
+    for change in changes {
+        println!("  {}", change);
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    dotenv::dotenv().ok();
+
+    let command = parse_command_line()?;
+    let mut store = SnapshotStore::from_env()?;
+    match command {
+        Command::Take { mode } => {
+            let previous = store.latest()?;
+            let auth_cache = AuthenticationCache::new()?;
+            let (synced, latest) = snapshot::take(&auth_cache, &mut store, mode)?;
+            print_synced(&synced);
+            println!(
+                "Saved snapshot {} at {}",
+                latest.id,
+                latest.taken_at.format("%Y-%m-%d %H:%M")
+            );
+            if let Some(previous) = previous {
+                println!(
+                    "Changes since snapshot {} at {}:",
+                    previous.id,
+                    previous.taken_at.format("%Y-%m-%d %H:%M")
+                );
+                print_changes(&snapshot::diff(&previous, &latest));
+            }
+        }
+        Command::Sync { mode } => {
+            let auth_cache = AuthenticationCache::new()?;
+            print_synced(&sync::sync(&auth_cache, &mut store, mode)?);
+        }
+        Command::Diff { between } => {
+            let (from, to) = match between {
+                Some(between) => between,
+                None => match store.list()?.as_slice() {
+                    [.., (from, _), (to, _)] => (*from, *to),
+                    _ => anyhow::bail!("there need to be two snapshots to compare"),
+                },
+            };
+            println!("Changes from snapshot {} to {}:", from, to);
+            print_changes(&snapshot::diff_between(&store, from, to)?);
+        }
+        Command::List => {
+            let snapshots = store.list()?;
+            if snapshots.is_empty() {
+                println!("There are no snapshots");
+            }
+            for (id, taken_at) in snapshots {
+                println!("{} taken at {}", id, taken_at.format("%Y-%m-%d %H:%M"));
+            }
+        }
+    }
+
+    Ok(())
+}
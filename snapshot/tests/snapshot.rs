@@ -0,0 +1,127 @@
+use fake_server::{FakeServer, Fixtures};
+use serde_json::json;
+use std::path::Path;
+
+fn snapshot(server: &FakeServer, database: &Path, args: &[&str]) -> String {
+    fake_server::run(
+        server
+            .command(env!("CARGO_BIN_EXE_snapshot"))
+            .env("SNAPSHOT_DB", database)
+            .args(args),
+    )
+}
+
+#[test]
+fn lists_what_changed_since_the_last_snapshot() {
+    let database =
+        std::env::temp_dir().join(format!("snapshot_test_{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&database);
+
+    let fixtures = Fixtures::examples().unwrap();
+    let server = FakeServer::start(fixtures.clone()).unwrap();
+    let first = snapshot(&server, &database, &["take"]);
+    assert!(first.contains("Saved snapshot 1"), "{}", first);
+    assert!(!first.contains("Changes since"), "{}", first);
+    drop(server);
+
+    // Overnight the wedding moved a day, the party was cancelled, the quote
+    // was accepted and the job was moved to another address.
+    let mut fixtures = fixtures;
+    for opportunity in fixtures.opportunities.iter_mut() {
+        opportunity["updated_at"] = json!("2021-03-21T09:00:00.000Z");
+    }
+    fixtures.opportunities[0]["starts_at"] = json!("2021-03-24T20:00:00.000Z");
+    fixtures.opportunities[1]["state"] = json!(2);
+    fixtures.opportunities[2]["state"] = json!(3);
+    fixtures.jobs[0]["job_address"] = json!("2 Queen Street, Auckland 1010");
+    fixtures.jobs[0]["edit_date"] = json!("2021-03-21 09:00:00");
+    fixtures.job_activities[1]["start_date"] = json!("2021-03-26 15:00:00");
+    fixtures.job_activities[1]["end_date"] = json!("2021-03-26 17:00:00");
+    fixtures.job_activities[1]["edit_date"] = json!("2021-03-21 09:00:00");
+    let server = FakeServer::start(fixtures).unwrap();
+    let second = snapshot(&server, &database, &["take"]);
+
+    assert!(second.contains("Saved snapshot 2"), "{}", second);
+    assert!(
+        second.contains("Synced job: 1 downloaded since 2021-03-20 08:59:59"),
+        "{}",
+        second
+    );
+    assert!(second.contains("Changes since snapshot 1"), "{}", second);
+    let expected = [
+        "opportunity 101: moved from 2021-03-23 20:00 - 2021-03-25 04:00 to 2021-03-24 20:00 - 2021-03-25 04:00",
+        "opportunity 103 (Conference quote): booked for 2021-03-01 20:00 - 2021-03-02 04:00",
+        "opportunity 102 (Birthday party): cancelled",
+        "job 1001: address changed from '1 Queen Street, Auckland 1010' to '2 Queen Street, Auckland 1010'",
+        "job 1001: activity moved from 2021-03-25 15:00 - 2021-03-25 17:00 to 2021-03-26 15:00 - 2021-03-26 17:00",
+    ];
+    for change in expected.iter() {
+        assert!(second.contains(change), "{}", second);
+    }
+
+    let diff = snapshot(&server, &database, &["diff"]);
+    assert!(diff.contains("Changes from snapshot 1 to 2"), "{}", diff);
+    assert!(diff.contains("(Birthday party): cancelled"), "{}", diff);
+    let list = snapshot(&server, &database, &["list"]);
+    assert_eq!(list.lines().count(), 2, "{}", list);
+    assert!(server.mutations().is_empty());
+
+    let _ = std::fs::remove_file(&database);
+}
+
+#[test]
+fn syncs_only_what_changed_and_notices_what_went() {
+    let database = std::env::temp_dir().join(format!("sync_test_{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&database);
+
+    let mut fixtures = Fixtures::examples().unwrap();
+    let server = FakeServer::start(fixtures.clone()).unwrap();
+    let first = snapshot(&server, &database, &["sync"]);
+    assert!(
+        first.contains("Synced opportunity: 3 downloaded in full"),
+        "{}",
+        first
+    );
+    assert!(
+        first.contains("Synced activity: 2 downloaded in full"),
+        "{}",
+        first
+    );
+    drop(server);
+
+    // The collection was deleted in ServiceM8 and the quote in Current RMS.
+    fixtures.job_activities[1]["active"] = json!(0);
+    fixtures.job_activities[1]["edit_date"] = json!("2021-03-21 09:00:00");
+    fixtures.opportunities.remove(2);
+    let server = FakeServer::start(fixtures).unwrap();
+    let second = snapshot(&server, &database, &["sync"]);
+    assert!(
+        second.contains(
+            "Synced activity: 2 downloaded since 2021-03-20 08:59:59, 1 made inactive, 0 deleted"
+        ),
+        "{}",
+        second
+    );
+    // Deletions can only be seen by downloading everything.
+    assert!(
+        second.contains(
+            "Synced opportunity: 2 downloaded since 2021-03-20 08:59:59, 0 made inactive, 0 deleted"
+        ),
+        "{}",
+        second
+    );
+
+    let third = snapshot(&server, &database, &["sync", "--full"]);
+    assert!(
+        third.contains("Synced opportunity: 2 downloaded in full, 0 made inactive, 1 deleted"),
+        "{}",
+        third
+    );
+    assert!(
+        third.contains("Synced activity: 2 downloaded in full, 0 made inactive, 0 deleted"),
+        "{}",
+        third
+    );
+
+    let _ = std::fs::remove_file(&database);
+}
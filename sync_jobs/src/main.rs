@@ -0,0 +1,113 @@
+// ---------------------------------------------------------------------
+// name: Sync Jobs
+// type: Command line application.
+// desc: Creates the servicem8 client, job, contact and provisional
+//       delivery and collection for each confirmed current-rms
+//       opportunity which hasn't been scheduled, or adds the missing
+//       leg to a job which only has one of them.
+// ---------------------------------------------------------------------
+//
+// usage:
+//   sync_jobs [--dry-run] [START [DAYS]]
+//
+// START is given as "2021-03-22 00:00:00" and defaults to today, DAYS to 7.
+// With --dry-run the records that would be created are listed and nothing is
+// changed.  An opportunity whose member has a job that can't be fixed by
+// adding a leg is reported and skipped, and one that fails doesn't stop the
+// rest.
+
+use chrono::prelude::*;
+use chrono::Duration;
+use schedule_assistant::authentication::AuthenticationCache;
+use schedule_assistant::booking::{self, Action, Client};
+use schedule_assistant::dataset::Dataset;
+use schedule_assistant::links::LinkStore;
+
+fn main() -> anyhow::Result<()> {
+    dotenv::dotenv().ok();
+
+    let mut args: Vec<String> = std::env::args().collect();
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    args.retain(|arg| arg != "--dry-run");
+    let start = if args.len() > 1 {
+        NaiveDateTime::parse_from_str(&args[1], "%Y-%m-%d %H:%M:%S")?.date()
+    } else {
+        Utc::now().date_naive()
+    };
+    let length = if args.len() > 2 {
+        args[2].parse::<i64>()?
+    } else {
+        7
+    };
+    let end = start + Duration::days(length);
+
+    let auth_cache = AuthenticationCache::new()?;
+    let (unscheduled, tables) = schedule_assistant::unscheduled_bookings(&auth_cache, start, end)?;
+    let links = LinkStore::from_env()?;
+    let dataset = Dataset::from_tables(&tables);
+
+    // Clients created along the way are added so that they are only created once.
+    let mut clients = tables.clients.clone();
+    let mut created = 0;
+    let mut completed = 0;
+    let mut failures = Vec::new();
+    for (opportunity, explanation) in &unscheduled {
+        println!("Opportunity {} ({})", opportunity.id, opportunity.subject);
+        let to_reconcile = dataset.job_to_reconcile(&links, opportunity);
+        let action = match booking::decide(opportunity, explanation, to_reconcile, &clients) {
+            Ok(action) => action,
+            Err(reason) => {
+                println!("  skipped, {}", reason);
+                continue;
+            }
+        };
+        print!("{}", action);
+        if dry_run {
+            // The client has no uuid yet, but is still only planned once.
+            if let Action::Create(plan) = &action {
+                if let Client::New { name, address } = &plan.client {
+                    clients.push(booking::new_company(name, address, "")?);
+                }
+            }
+            continue;
+        }
+        let result = match &action {
+            Action::Create(plan) => booking::create(&auth_cache, plan, &mut clients).map(|job| {
+                println!("  created job {}", job);
+                created += 1;
+            }),
+            Action::AddVisits { job, visits } => visits
+                .iter()
+                .try_for_each(|visit| booking::add_visit(&auth_cache, job, visit).map(|_| ()))
+                .map(|_| completed += 1),
+        };
+        if let Err(e) = result {
+            println!("  failed, {}", e);
+            failures.push((opportunity.id, e));
+        }
+    }
+
+    if dry_run {
+        println!(
+            "Dry run, {} unscheduled opportunities and nothing was changed",
+            unscheduled.len()
+        );
+    } else {
+        println!(
+            "Created jobs for {} of {} unscheduled opportunities",
+            created,
+            unscheduled.len()
+        );
+        if completed > 0 {
+            println!("Added the missing legs to {} jobs", completed);
+        }
+    }
+
+    if !failures.is_empty() {
+        for (opportunity_id, e) in &failures {
+            eprintln!("opportunity {}: {}", opportunity_id, e);
+        }
+        anyhow::bail!("{} opportunities couldn't be scheduled", failures.len());
+    }
+    Ok(())
+}
@@ -0,0 +1,265 @@
+use fake_server::{Failure, FakeServer, Fixtures};
+use serde_json::json;
+
+fn sync_jobs(server: &FakeServer, args: &[&str]) -> String {
+    fake_server::run(server.command(env!("CARGO_BIN_EXE_sync_jobs")).args(args))
+}
+
+#[test]
+fn a_dry_run_changes_nothing() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    let stdout = sync_jobs(&server, &["--dry-run", "2021-03-22 00:00:00", "7"]);
+
+    // Only the party is unscheduled, and Acme isn't a client yet.
+    assert!(!stdout.contains("Opportunity 101"), "{}", stdout);
+    let expected = [
+        "Opportunity 102 (Birthday party)",
+        "  create the client Acme Limited",
+        "  create a job at 20 Ponsonby Road, Auckland, Auckland, 1011",
+        "  add the contact Acme Limited (events@acme.example.com)",
+        "  add a provisional delivery from 2021-03-24 19:00 to 2021-03-24 21:00",
+        "  add a provisional collection from 2021-03-26 03:00 to 2021-03-26 05:00",
+        "Dry run, 1 unscheduled opportunities and nothing was changed",
+    ];
+    for line in expected.iter() {
+        assert!(stdout.contains(line), "{}", stdout);
+    }
+    assert!(server.mutations().is_empty());
+}
+
+#[test]
+fn creates_the_records_for_unscheduled_orders() {
+    let server = FakeServer::start(Fixtures::examples().unwrap()).unwrap();
+    let stdout = sync_jobs(&server, &["2021-03-22 00:00:00", "7"]);
+    assert!(
+        stdout.contains("Created jobs for 1 of 1 unscheduled opportunities"),
+        "{}",
+        stdout
+    );
+
+    let mutations = server.mutations();
+    let paths: Vec<(&str, &str)> = mutations
+        .iter()
+        .map(|m| (m.method.as_str(), m.path.as_str()))
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            ("POST", "/servicem8/api_1.0/company.json"),
+            ("POST", "/servicem8/api_1.0/job.json"),
+            ("POST", "/servicem8/api_1.0/jobcontact.json"),
+            ("POST", "/servicem8/api_1.0/jobactivity.json"),
+            ("POST", "/servicem8/api_1.0/jobactivity.json"),
+            ("PUT", "/current/api/v1/opportunities/102"),
+        ]
+    );
+    let job = &mutations[1].body;
+    assert_eq!(job["purchase_order_number"], "RMS#102");
+    assert_eq!(
+        job["job_address"],
+        "20 Ponsonby Road, Auckland, Auckland, 1011"
+    );
+    assert_eq!(mutations[3].body["start_date"], "2021-03-24 19:00:00");
+    assert_eq!(mutations[4].body["end_date"], "2021-03-26 05:00:00");
+
+    // The next run finds the job that was created.
+    let stdout = sync_jobs(&server, &["2021-03-22 00:00:00", "7"]);
+    assert!(
+        stdout.contains("Created jobs for 0 of 0 unscheduled opportunities"),
+        "{}",
+        stdout
+    );
+}
+
+// Acme as a client with a job for the party which only has the delivery.
+fn with_delivery_only_job() -> Fixtures {
+    let mut fixtures = Fixtures::examples().unwrap();
+    fixtures.companies.push(json!({
+        "uuid": "c0000000-0000-0000-0000-000000000002",
+        "edit_date": "2021-03-20 09:00:00",
+        "active": 1,
+        "name": "Acme Ltd",
+        "address": "20 Ponsonby Road, Auckland 1011",
+    }));
+    fixtures.jobs.push(json!({
+        "uuid": "j0000000-0000-0000-0000-000000000002",
+        "edit_date": "2021-03-20 09:00:00",
+        "active": 1,
+        "company_uuid": "c0000000-0000-0000-0000-000000000002",
+        "job_address": "20 Ponsonby Road, Auckland 1011",
+        "status": "Work Order",
+        "generated_job_id": "1002",
+        "purchase_order_number": "",
+        "job_description": "Birthday party",
+        "date": "2021-03-24 00:00:00",
+    }));
+    fixtures.job_activities.push(json!({
+        "uuid": "a0000000-0000-0000-0000-000000000003",
+        "edit_date": "2021-03-20 09:00:00",
+        "active": 1,
+        "job_uuid": "j0000000-0000-0000-0000-000000000002",
+        "staff_uuid": "s0000000-0000-0000-0000-000000000001",
+        "start_date": "2021-03-24 19:00:00",
+        "end_date": "2021-03-24 21:00:00",
+        "activity_was_scheduled": 1,
+    }));
+    fixtures
+}
+
+#[test]
+fn only_the_missing_leg_is_added_to_an_existing_job() {
+    let server = FakeServer::start(with_delivery_only_job()).unwrap();
+    let stdout = sync_jobs(&server, &["2021-03-22 00:00:00", "7"]);
+    assert!(
+        stdout.contains(
+            "  add a provisional collection from 2021-03-26 03:00 to 2021-03-26 05:00 \
+             to job j0000000-0000-0000-0000-000000000002"
+        ),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("Added the missing legs to 1 jobs"),
+        "{}",
+        stdout
+    );
+
+    let mutations = server.mutations();
+    let paths: Vec<(&str, &str)> = mutations
+        .iter()
+        .map(|m| (m.method.as_str(), m.path.as_str()))
+        .collect();
+    assert_eq!(paths, [("POST", "/servicem8/api_1.0/jobactivity.json")]);
+    assert_eq!(
+        mutations[0].body["job_uuid"],
+        "j0000000-0000-0000-0000-000000000002"
+    );
+    assert_eq!(mutations[0].body["start_date"], "2021-03-26 03:00:00");
+}
+
+#[test]
+fn a_job_which_has_drifted_is_reported_rather_than_booked_again() {
+    let mut fixtures = with_delivery_only_job();
+    fixtures.job_activities[2]["start_date"] = json!("2021-03-29 19:00:00");
+    fixtures.job_activities[2]["end_date"] = json!("2021-03-29 21:00:00");
+    let server = FakeServer::start(fixtures).unwrap();
+    let stdout = sync_jobs(&server, &["2021-03-22 00:00:00", "7"]);
+    assert!(
+        stdout.contains(
+            "  skipped, the member already has job j0000000-0000-0000-0000-000000000002 but \
+             none of the 1 activities are near the dates of the opportunity"
+        ),
+        "{}",
+        stdout
+    );
+    assert!(server.mutations().is_empty());
+}
+
+// A second party for Acme in the same week.
+fn with_second_party() -> Fixtures {
+    let mut fixtures = Fixtures::examples().unwrap();
+    let mut party = fixtures
+        .opportunities
+        .iter()
+        .find(|opportunity| opportunity["id"] == 102)
+        .unwrap()
+        .clone();
+    party["id"] = json!(104);
+    party["subject"] = json!("Anniversary");
+    party["starts_at"] = json!("2021-03-26T21:00:00.000Z");
+    party["ends_at"] = json!("2021-03-27T03:00:00.000Z");
+    fixtures.opportunities.push(party);
+    fixtures
+}
+
+fn posted(server: &FakeServer, table: &str) -> Vec<serde_json::Value> {
+    let path = format!("/servicem8/api_1.0/{}.json", table);
+    server
+        .mutations()
+        .into_iter()
+        .filter(|m| m.method == "POST" && m.path == path)
+        .map(|m| m.body)
+        .collect()
+}
+
+#[test]
+fn a_new_client_is_only_created_once() {
+    let server = FakeServer::start(with_second_party()).unwrap();
+    let stdout = sync_jobs(&server, &["--dry-run", "2021-03-22 00:00:00", "7"]);
+    assert_eq!(
+        stdout.matches("  create the client Acme Limited").count(),
+        1,
+        "{}",
+        stdout
+    );
+
+    let stdout = sync_jobs(&server, &["2021-03-22 00:00:00", "7"]);
+    assert!(
+        stdout.contains("Created jobs for 2 of 2 unscheduled opportunities"),
+        "{}",
+        stdout
+    );
+    assert_eq!(posted(&server, "company").len(), 1);
+    let jobs = posted(&server, "job");
+    assert_eq!(jobs.len(), 2);
+    assert_eq!(jobs[0]["company_uuid"], jobs[1]["company_uuid"]);
+}
+
+#[test]
+fn a_failed_opportunity_does_not_stop_the_others() {
+    let server = FakeServer::start(with_second_party()).unwrap();
+    server.fail(Failure::new("POST", "/servicem8/api_1.0/job.json", 500).times(1));
+    let output = server
+        .command(env!("CARGO_BIN_EXE_sync_jobs"))
+        .args(["2021-03-22 00:00:00", "7"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success());
+    assert!(stderr.contains("opportunity 102:"), "{}", stderr);
+    assert!(
+        stdout.contains("Created jobs for 1 of 2 unscheduled opportunities"),
+        "{}",
+        stdout
+    );
+
+    // The client made for the first is used for the second.
+    assert_eq!(posted(&server, "company").len(), 1);
+    assert_eq!(posted(&server, "job").len(), 1);
+}
+
+#[test]
+fn a_job_left_without_activities_is_finished_rather_than_booked_again() {
+    let mut fixtures = with_delivery_only_job();
+    fixtures.job_activities.pop();
+    fixtures.jobs[1]["purchase_order_number"] = json!("RMS#102");
+    let server = FakeServer::start(fixtures).unwrap();
+    sync_jobs(&server, &["2021-03-22 00:00:00", "7"]);
+
+    assert!(posted(&server, "job").is_empty());
+    let activities = posted(&server, "jobactivity");
+    assert_eq!(activities.len(), 2);
+    for activity in &activities {
+        assert_eq!(activity["job_uuid"], "j0000000-0000-0000-0000-000000000002");
+    }
+}
+
+#[test]
+fn a_client_with_a_similar_name_is_not_reused() {
+    let mut fixtures = Fixtures::examples().unwrap();
+    fixtures.companies.push(json!({
+        "uuid": "c0000000-0000-0000-0000-000000000002",
+        "edit_date": "2021-03-20 09:00:00",
+        "active": 1,
+        "name": "Acme Hire Limited",
+        "address": "5 Great North Road, Auckland 1021",
+    }));
+    let server = FakeServer::start(fixtures).unwrap();
+    let stdout = sync_jobs(&server, &["--dry-run", "2021-03-22 00:00:00", "7"]);
+    assert!(
+        stdout.contains("  create the client Acme Limited"),
+        "{}",
+        stdout
+    );
+}